@@ -0,0 +1,436 @@
+//! Compact binary statistics format.
+//!
+//! Holds exactly what the YAML format does, so files convert both ways without loss,
+//! but every key name is written once in a table and referred to by its index,
+//! and every number is a LEB128 varint (durations are little endian `f64`).
+//!
+//! ```text
+//! file     = "HKCS" version:u8 config journal:varint keys sections dwell flight
+//! config   = 0 | 1 pairs:u8 ngram:varint no_chords:u8 modes dwell:u8 flight:u8 version:string
+//! keys     = len:varint string*
+//! sections = len:varint (mode len:varint (item count:varint)*)*
+//! dwell    = len:varint (key:varint stats)*
+//! flight   = len:varint (mode len:varint (item stats)*)*
+//! item     = inputs:varint (len:varint key:varint*)*
+//! mode     = ngram:varint chords:u8
+//! ```
+
+use crate::counts::{
+    keycode_to_string, parse_keycode_from_string, Config, CountItem, HashMap, KeyCounts, Mode,
+};
+use crate::stats::{DurationStats, Percentiles};
+use device_query::Keycode;
+
+const MAGIC: &[u8; 4] = b"HKCS";
+pub const BINARY_VERSION: u8 = 1;
+
+/// Contents of a binary statistics file.
+pub fn encode(key_counts: &KeyCounts) -> Vec<u8> {
+    let mut keys: Vec<Keycode> = Vec::new();
+    let mut index = |key: &Keycode| match keys.iter().position(|known| known == key) {
+        Some(index) => index,
+        None => {
+            keys.push(*key);
+            keys.len() - 1
+        }
+    };
+
+    // keys are collected while the body is written, the table goes before it
+    let mut body = Writer::default();
+    body.varint(key_counts.sections.len() as u64);
+    for (mode, counts) in &key_counts.sections {
+        body.mode(mode);
+        body.varint(counts.len() as u64);
+        for (count_item, count) in counts {
+            body.count_item(count_item, &mut index);
+            body.varint(*count as u64);
+        }
+    }
+
+    body.varint(key_counts.dwell.len() as u64);
+    for (key, stats) in &key_counts.dwell {
+        body.varint(index(key) as u64);
+        body.stats(stats);
+    }
+
+    body.varint(key_counts.flight.len() as u64);
+    for (mode, flight) in &key_counts.flight {
+        body.mode(mode);
+        body.varint(flight.len() as u64);
+        for (count_item, stats) in flight {
+            body.count_item(count_item, &mut index);
+            body.stats(stats);
+        }
+    }
+
+    let mut file = Writer::default();
+    file.bytes.extend_from_slice(MAGIC);
+    file.bytes.push(BINARY_VERSION);
+    file.config(key_counts.config.as_ref());
+    file.varint(key_counts.journal);
+    file.varint(keys.len() as u64);
+    for key in &keys {
+        file.string(&keycode_to_string(key));
+    }
+    file.bytes.extend(body.bytes);
+
+    file.bytes
+}
+
+/// Parse the contents of a binary statistics file.
+pub fn decode(bytes: &[u8]) -> Result<KeyCounts, String> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err("not a binary statistics file".into());
+    }
+    let version = reader.byte()?;
+    if version != BINARY_VERSION {
+        return Err(format!(
+            "binary format version {} is not supported, only {} is",
+            version, BINARY_VERSION
+        ));
+    }
+
+    let mut key_counts = KeyCounts::new();
+    key_counts.config = reader.config()?;
+    key_counts.journal = reader.varint()?;
+
+    let keys = (0..reader.varint()?)
+        .map(|_| parse_keycode_from_string(&reader.string()?))
+        .collect::<Result<Vec<Keycode>, String>>()?;
+
+    for _ in 0..reader.varint()? {
+        let mode = reader.mode()?;
+        let mut counts = HashMap::new();
+        for _ in 0..reader.varint()? {
+            let count_item = reader.count_item(&keys)?;
+            let count = u32::try_from(reader.varint()?).map_err(|error| error.to_string())?;
+            counts.insert(count_item, count);
+        }
+        key_counts.sections.insert(mode, counts);
+    }
+
+    for _ in 0..reader.varint()? {
+        let key = reader.key(&keys)?;
+        key_counts.dwell.insert(key, reader.stats()?);
+    }
+
+    for _ in 0..reader.varint()? {
+        let mode = reader.mode()?;
+        let mut flight = HashMap::new();
+        for _ in 0..reader.varint()? {
+            let count_item = reader.count_item(&keys)?;
+            flight.insert(count_item, reader.stats()?);
+        }
+        key_counts.flight.insert(mode, flight);
+    }
+
+    if reader.position != bytes.len() {
+        return Err("unexpected data after the end".into());
+    }
+
+    Ok(key_counts)
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn varint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+
+    fn float(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.varint(value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn mode(&mut self, mode: &Mode) {
+        self.varint(mode.ngram as u64);
+        self.bool(mode.chords);
+    }
+
+    fn config(&mut self, config: Option<&Config>) {
+        let Some(config) = config else {
+            self.bool(false);
+            return;
+        };
+
+        self.bool(true);
+        self.bool(config.pairs);
+        self.varint(config.ngram.unwrap_or(0) as u64);
+        self.bool(config.no_chords);
+        self.varint(config.collect.len() as u64);
+        for mode in &config.collect {
+            self.mode(mode);
+        }
+        self.bool(config.dwell);
+        self.bool(config.flight);
+        self.string(&config.version);
+    }
+
+    fn count_item(&mut self, count_item: &CountItem, index: &mut impl FnMut(&Keycode) -> usize) {
+        let inputs = count_item.inputs();
+        self.varint(inputs.len() as u64);
+        for input in inputs {
+            self.varint(input.len() as u64);
+            for key in input {
+                self.varint(index(key) as u64);
+            }
+        }
+    }
+
+    fn stats(&mut self, stats: &DurationStats) {
+        self.varint(stats.count);
+        self.float(stats.mean_ms);
+        self.float(stats.variance_ms2);
+        self.float(stats.min_ms);
+        self.float(stats.max_ms);
+        self.float(stats.percentiles.p50);
+        self.float(stats.percentiles.p90);
+        self.float(stats.percentiles.p99);
+        self.varint(stats.histogram.len() as u64);
+        for bucket in &stats.histogram {
+            self.varint(*bucket);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or("unexpected end of data")?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("varint is too long".into())
+    }
+
+    fn usize(&mut self) -> Result<usize, String> {
+        usize::try_from(self.varint()?).map_err(|error| error.to_string())
+    }
+
+    fn bool(&mut self) -> Result<bool, String> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(format!("{} is not a boolean", byte)),
+        }
+    }
+
+    fn float(&mut self) -> Result<f64, String> {
+        let bytes = self.take(8)?;
+        Ok(f64::from_le_bytes(bytes.try_into().expect("8 bytes taken")))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.usize()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|error| error.to_string())
+    }
+
+    fn mode(&mut self) -> Result<Mode, String> {
+        let ngram = self.usize()?;
+        Ok(Mode::new(ngram, self.bool()?))
+    }
+
+    fn config(&mut self) -> Result<Option<Config>, String> {
+        if !self.bool()? {
+            return Ok(None);
+        }
+
+        let pairs = self.bool()?;
+        let ngram = Some(self.usize()?).filter(|ngram| *ngram != 0);
+        let no_chords = self.bool()?;
+        let collect = (0..self.varint()?)
+            .map(|_| self.mode())
+            .collect::<Result<Vec<Mode>, String>>()?;
+
+        Ok(Some(Config {
+            pairs,
+            ngram,
+            no_chords,
+            collect,
+            dwell: self.bool()?,
+            flight: self.bool()?,
+            version: self.string()?,
+        }))
+    }
+
+    fn key(&mut self, keys: &[Keycode]) -> Result<Keycode, String> {
+        let index = self.usize()?;
+        keys.get(index)
+            .copied()
+            .ok_or_else(|| format!("key index {} is out of the table", index))
+    }
+
+    fn count_item(&mut self, keys: &[Keycode]) -> Result<CountItem, String> {
+        let mut inputs = (0..self.varint()?)
+            .map(|_| {
+                (0..self.varint()?)
+                    .map(|_| self.key(keys))
+                    .collect::<Result<Vec<Keycode>, String>>()
+            })
+            .collect::<Result<Vec<Vec<Keycode>>, String>>()?;
+
+        match inputs.len() {
+            0 => Err("count item without inputs".into()),
+            1 => Ok(CountItem::Single(inputs.remove(0))),
+            2 => {
+                let second = inputs.remove(1);
+                Ok(CountItem::Pair(inputs.remove(0), second))
+            }
+            _ => Ok(CountItem::Ngram(inputs)),
+        }
+    }
+
+    fn stats(&mut self) -> Result<DurationStats, String> {
+        Ok(DurationStats {
+            count: self.varint()?,
+            mean_ms: self.float()?,
+            variance_ms2: self.float()?,
+            min_ms: self.float()?,
+            max_ms: self.float()?,
+            percentiles: Percentiles {
+                p50: self.float()?,
+                p90: self.float()?,
+                p99: self.float()?,
+            },
+            histogram: (0..self.varint()?)
+                .map(|_| self.varint())
+                .collect::<Result<Vec<u64>, String>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn key_counts() -> KeyCounts {
+        let chords = Mode::new(1, true);
+        let keys = Mode::new(1, false);
+        let chord_pairs = Mode::new(2, true);
+        let chord_trigrams = Mode::new(3, true);
+
+        let mut key_counts = KeyCounts::new();
+        key_counts.set_config(Config::collecting(
+            vec![chords, keys, chord_pairs, chord_trigrams],
+            true,
+            true,
+            "1.1.0".into(),
+        ));
+        key_counts.journal = 7;
+
+        let a = vec![Keycode::A];
+        let shift_a = vec![Keycode::A, Keycode::LShift];
+        let b = vec![Keycode::B];
+        key_counts
+            .counts_mut(chords)
+            .insert(CountItem::Single(shift_a.clone()), 300);
+        key_counts
+            .counts_mut(keys)
+            .insert(CountItem::Single(a.clone()), 1);
+        key_counts
+            .counts_mut(chord_pairs)
+            .insert(CountItem::Pair(shift_a.clone(), b.clone()), 2);
+        key_counts.counts_mut(chord_trigrams).insert(
+            CountItem::Ngram(vec![a.clone(), shift_a.clone(), b.clone()]),
+            u32::MAX,
+        );
+
+        for hold in [12, 95, 1500] {
+            key_counts
+                .dwell
+                .entry(Keycode::LShift)
+                .or_default()
+                .record(Duration::from_micros(hold * 1001));
+        }
+        key_counts
+            .flight
+            .entry(chord_pairs)
+            .or_default()
+            .entry(CountItem::Pair(shift_a, b))
+            .or_default()
+            .record(Duration::from_nanos(123_456_789));
+
+        key_counts
+    }
+
+    #[test]
+    fn round_trip() {
+        let key_counts = key_counts();
+        let decoded = decode(&encode(&key_counts)).unwrap();
+
+        assert_eq!(decoded.to_yaml().unwrap(), key_counts.to_yaml().unwrap());
+        assert_eq!(decoded.journal, key_counts.journal);
+    }
+
+    #[test]
+    fn round_trip_from_yaml() {
+        let yaml = key_counts().to_yaml().unwrap();
+        let from_yaml = KeyCounts::from_yaml(&yaml).unwrap();
+
+        let decoded = decode(&encode(&from_yaml)).unwrap();
+        assert_eq!(decoded.to_yaml().unwrap(), yaml);
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = encode(&key_counts());
+
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "{} bytes decoded", len);
+        }
+    }
+
+    #[test]
+    fn data_after_the_end() {
+        let mut bytes = encode(&key_counts());
+        bytes.push(0);
+
+        assert!(decode(&bytes).is_err());
+    }
+}
@@ -0,0 +1,599 @@
+//! Counting pipeline shared by every [`KeySource`][crate::source::KeySource].
+//!
+//! Every count and key hold is logged at info level.
+
+use crate::counts::{CountItem, HashMap, KeyCounts, Mode};
+use crate::error::Result;
+use crate::format::Format;
+use crate::journal::{Entry, Journal};
+use crate::keycode;
+use crate::source::{KeyEvent, KeyEventKind};
+#[cfg(feature = "sqlite")]
+use crate::sqlite::Database;
+use crate::trace::{append_event, upend_trace, TraceEvent, TraceFormat, TraceStep};
+use device_query::Keycode;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Longest time captured counts stay unsaved by default.
+pub const DEFAULT_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Most updates kept unsaved by default.
+pub const DEFAULT_SAVE_EVERY: u64 = 1000;
+
+/// Counting pipeline: turns key events into counts and trace steps.
+pub struct Capture {
+    pub key_counts: KeyCounts,
+    pub statistic_path: Option<PathBuf>,
+    /// Format the statistics file is saved in.
+    pub format: Format,
+    pub trace_path: Option<PathBuf>,
+    pub trace_format: TraceFormat,
+    pub dwell: bool,
+    pub flight: bool,
+    /// Save once the oldest unsaved update is that old.
+    pub save_interval: Duration,
+    /// Save once that many updates are unsaved.
+    pub save_every: u64,
+    /// Where every update is written at once, compacted on each save.
+    pub journal: Option<Journal>,
+    /// Database and id of the session the trace is appended to instead of `trace_path`.
+    #[cfg(feature = "sqlite")]
+    pub trace_session: Option<(Database, i64)>,
+    held: Vec<Keycode>,
+    pressed_at: HashMap<Keycode, Duration>,
+    modes: Vec<Mode>,
+    /// Per mode, up to `ngram - 1` last inputs with the time they were pressed.
+    last_inputs: Vec<VecDeque<(Vec<Keycode>, Duration)>>,
+    first_trace_step: bool,
+    trace_start: Duration,
+    /// Wall clock time of the first traced event.
+    trace_wall: SystemTime,
+    last_time: Duration,
+    inputs: u64,
+    unsaved: u64,
+    last_save: Instant,
+    /// Updates of the current batch not yet in the journal.
+    journal_entries: Vec<Entry>,
+}
+
+impl Capture {
+    /// Count `modes` into `key_counts`, without saving or tracing anything.
+    pub fn new(key_counts: KeyCounts, modes: Vec<Mode>) -> Self {
+        Self {
+            key_counts,
+            statistic_path: None,
+            format: Format::default(),
+            trace_path: None,
+            trace_format: TraceFormat::default(),
+            dwell: false,
+            flight: false,
+            save_interval: DEFAULT_SAVE_INTERVAL,
+            save_every: DEFAULT_SAVE_EVERY,
+            journal: None,
+            #[cfg(feature = "sqlite")]
+            trace_session: None,
+            held: Vec::new(),
+            pressed_at: HashMap::new(),
+            last_inputs: vec![VecDeque::new(); modes.len()],
+            modes,
+            first_trace_step: true,
+            trace_start: Duration::ZERO,
+            trace_wall: SystemTime::UNIX_EPOCH,
+            last_time: Duration::ZERO,
+            inputs: 0,
+            unsaved: 0,
+            last_save: Instant::now(),
+            journal_entries: Vec::new(),
+        }
+    }
+
+    /// How many inputs have been captured, keys pressed together count once.
+    pub fn inputs(&self) -> u64 {
+        self.inputs
+    }
+
+    /// Forget the inputs before, so n-grams and flight times of a capture
+    /// session that starts now do not reach back into the previous one.
+    pub fn start_session(&mut self) {
+        for last_inputs in &mut self.last_inputs {
+            last_inputs.clear();
+        }
+    }
+
+    /// Write everything captured so far, to be called before capture stops.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.unsaved == 0 {
+            return self.flush_trace();
+        }
+        self.save()
+    }
+
+    /// Apply a batch of events; events with equal time are handled as one step,
+    /// so keys pressed together make one chord.
+    ///
+    /// Counts are saved when [`Self::save_every`] updates or [`Self::save_interval`]
+    /// have passed since the last save, so an empty batch may save too.
+    pub fn update(&mut self, events: Vec<KeyEvent>) -> Result<()> {
+        let mut events = events.into_iter().peekable();
+        while let Some(first) = events.next() {
+            let mut step = vec![first];
+            while let Some(event) = events.next_if(|event| event.time == first.time) {
+                step.push(event);
+            }
+            self.step(step, first.time)?;
+        }
+
+        if let Some(ref mut journal) = self.journal {
+            for entry in self.journal_entries.drain(..) {
+                journal.append(&entry)?;
+            }
+        }
+
+        let due = self.unsaved >= self.save_every
+            || (self.unsaved > 0 && self.last_save.elapsed() >= self.save_interval);
+        if due {
+            self.save()?;
+        }
+
+        Ok(())
+    }
+
+    fn step(&mut self, events: Vec<KeyEvent>, time: Duration) -> Result<()> {
+        let mut pressed = Vec::new();
+        let mut released = Vec::new();
+
+        for event in events {
+            match event.kind {
+                KeyEventKind::Press => {
+                    if !self.held.contains(&event.key) {
+                        self.held.push(event.key);
+                        self.pressed_at.insert(event.key, time);
+                        pressed.push(event.key);
+                    }
+                }
+                KeyEventKind::Release => {
+                    self.held.retain(|key| *key != event.key);
+                    if let Some(pressed_at) = self.pressed_at.remove(&event.key) {
+                        self.release(event.key, time.saturating_sub(pressed_at));
+                        released.push(event.key);
+                    }
+                }
+            }
+        }
+
+        if !released.is_empty() && self.trace_format == TraceFormat::Json {
+            keycode::sort_keys(&mut released);
+            self.trace_event(KeyEventKind::Release, released, time)?;
+        }
+
+        if pressed.is_empty() {
+            return Ok(());
+        }
+        self.inputs += 1;
+
+        keycode::sort_keys(&mut self.held);
+        keycode::sort_keys(&mut pressed);
+
+        for index in 0..self.modes.len() {
+            if self.modes[index].chords {
+                self.press(index, self.held.clone(), time);
+            } else {
+                for key in &pressed {
+                    self.press(index, vec![*key], time);
+                }
+            }
+        }
+
+        // trace chords unless only separate keys are counted
+        if self.modes.iter().any(|mode| mode.chords) {
+            self.trace(self.held.clone(), time)?;
+        } else {
+            for key in pressed {
+                self.trace(vec![key], time)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn press(&mut self, index: usize, input: Vec<Keycode>, time: Duration) {
+        let mode = self.modes[index];
+        let last_inputs = &mut self.last_inputs[index];
+
+        // skip first inputs becouse they are have not enough previous ones
+        let count_item = if last_inputs.len() + 1 == mode.ngram {
+            Some(match mode.ngram {
+                1 => CountItem::Single(input.clone()),
+                2 => {
+                    let (last_input, last_time) = &last_inputs[0];
+                    let count_item = CountItem::Pair(last_input.clone(), input.clone());
+
+                    if self.flight {
+                        let flight = time.saturating_sub(*last_time);
+                        if self.journal.is_some() {
+                            self.journal_entries.push(Entry::Flight(
+                                mode,
+                                count_item.clone(),
+                                flight,
+                            ));
+                        }
+                        self.key_counts
+                            .flight
+                            .entry(mode)
+                            .or_default()
+                            .entry(count_item.clone())
+                            .or_default()
+                            .record(flight);
+                    }
+
+                    count_item
+                }
+                _ => CountItem::Ngram(
+                    last_inputs
+                        .iter()
+                        .map(|(input, _)| input.clone())
+                        .chain(std::iter::once(input.clone()))
+                        .collect(),
+                ),
+            })
+        } else {
+            None
+        };
+
+        if mode.ngram > 1 {
+            if last_inputs.len() + 1 == mode.ngram {
+                last_inputs.pop_front();
+            }
+            last_inputs.push_back((input, time));
+        }
+
+        if let Some(count_item) = count_item {
+            self.count(mode, count_item);
+        }
+    }
+
+    fn release(&mut self, key: Keycode, hold: Duration) {
+        log::info!("{:?} has been held for {:?}", key, hold);
+
+        if self.dwell {
+            self.key_counts.dwell.entry(key).or_default().record(hold);
+            self.journal(Entry::Dwell(key, hold));
+            self.unsaved += 1;
+        }
+    }
+
+    fn count(&mut self, mode: Mode, count_item: CountItem) {
+        let count = self
+            .key_counts
+            .counts_mut(mode)
+            .entry(count_item.clone())
+            .or_insert(0);
+        *count += 1;
+
+        log::info!("{:?} has been pressed {} times", count_item, count);
+
+        self.journal(Entry::Count(mode, count_item));
+        self.unsaved += 1;
+    }
+
+    fn journal(&mut self, entry: Entry) {
+        if self.journal.is_some() {
+            self.journal_entries.push(entry);
+        }
+    }
+
+    fn save(&mut self) -> Result<()> {
+        if let Some(ref path) = self.statistic_path {
+            if let Some(ref journal) = self.journal {
+                self.key_counts.journal = journal.generation();
+            }
+            self.key_counts.save_as(path, self.format)?;
+            if let Some(ref mut journal) = self.journal {
+                journal.compact()?;
+            }
+        }
+        self.flush_trace()?;
+        self.unsaved = 0;
+        self.last_save = Instant::now();
+
+        Ok(())
+    }
+
+    /// Write trace steps buffered by the trace database, if any.
+    fn flush_trace(&mut self) -> Result<()> {
+        #[cfg(feature = "sqlite")]
+        if let Some((ref mut database, _)) = self.trace_session {
+            database.flush_trace()?;
+        }
+
+        Ok(())
+    }
+
+    fn trace(&mut self, keys: Vec<Keycode>, time: Duration) -> Result<()> {
+        #[cfg(feature = "sqlite")]
+        if let Some((ref mut database, session)) = self.trace_session {
+            let (since_first, after) = if self.first_trace_step {
+                self.first_trace_step = false;
+                self.trace_start = time;
+                (Duration::ZERO, None)
+            } else {
+                (
+                    time.saturating_sub(self.trace_start),
+                    Some(time.saturating_sub(self.last_time)),
+                )
+            };
+
+            self.last_time = time;
+
+            return database.append_trace(session, &keys, since_first, after);
+        }
+
+        if self.trace_format == TraceFormat::Json {
+            return self.trace_event(KeyEventKind::Press, keys, time);
+        }
+
+        if let Some(ref trace_path) = self.trace_path {
+            let step = if self.first_trace_step {
+                self.first_trace_step = false;
+                TraceStep::First(keys)
+            } else {
+                TraceStep::Regular(keys, time.saturating_sub(self.last_time))
+            };
+
+            self.last_time = time;
+
+            upend_trace(step, trace_path, self.trace_format == TraceFormat::Plain)?;
+        }
+
+        Ok(())
+    }
+
+    fn trace_event(
+        &mut self,
+        kind: KeyEventKind,
+        keys: Vec<Keycode>,
+        time: Duration,
+    ) -> Result<()> {
+        let Some(ref trace_path) = self.trace_path else {
+            return Ok(());
+        };
+        #[cfg(feature = "sqlite")]
+        if self.trace_session.is_some() {
+            return Ok(());
+        }
+
+        if self.first_trace_step {
+            self.first_trace_step = false;
+            self.trace_start = time;
+            self.trace_wall = SystemTime::now();
+            self.last_time = time;
+        }
+        // later events are placed by their own timestamps, not by when they are handled
+        let since_start = time.saturating_sub(self.trace_start);
+        let event = TraceEvent {
+            kind,
+            keys,
+            time: since_start,
+            wall: self.trace_wall + since_start,
+            delta: time.saturating_sub(self.last_time),
+        };
+
+        self.last_time = time;
+
+        append_event(&event, trace_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{KeySource, KeyState, ScriptedSource};
+    use crate::temp::TempFile;
+    use std::fs;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn single(keys: &[Keycode]) -> CountItem {
+        CountItem::Single(keys.to_vec())
+    }
+
+    /// Counts of everything `source` reports under `modes`.
+    fn run(mut source: impl KeySource, modes: Vec<Mode>) -> KeyCounts {
+        let mut capture = Capture::new(KeyCounts::new(), modes);
+        while let Some(events) = source.poll() {
+            capture.update(events).unwrap();
+        }
+        capture.flush().unwrap();
+        capture.key_counts
+    }
+
+    /// `A` typed with `LShift` held, then `B` alone.
+    fn shifted_a_then_b() -> ScriptedSource {
+        ScriptedSource::new([
+            KeyEvent::press(Keycode::LShift, ms(0)),
+            KeyEvent::press(Keycode::A, ms(10)),
+            KeyEvent::release(Keycode::A, ms(20)),
+            KeyEvent::release(Keycode::LShift, ms(30)),
+            KeyEvent::press(Keycode::B, ms(40)),
+            KeyEvent::release(Keycode::B, ms(50)),
+        ])
+    }
+
+    #[test]
+    fn singles() {
+        let keys = Mode::new(1, false);
+        let key_counts = run(shifted_a_then_b(), vec![keys]);
+
+        assert_eq!(key_counts.get(&keys, &single(&[Keycode::LShift])), 1);
+        assert_eq!(key_counts.get(&keys, &single(&[Keycode::A])), 1);
+        assert_eq!(key_counts.get(&keys, &single(&[Keycode::B])), 1);
+        assert_eq!(key_counts.total(&keys), 3);
+    }
+
+    #[test]
+    fn chords() {
+        let chords = Mode::new(1, true);
+        let key_counts = run(shifted_a_then_b(), vec![chords]);
+
+        assert_eq!(key_counts.get(&chords, &single(&[Keycode::LShift])), 1);
+        assert_eq!(
+            key_counts.get(&chords, &single(&[Keycode::A, Keycode::LShift])),
+            1
+        );
+        assert_eq!(key_counts.get(&chords, &single(&[Keycode::B])), 1);
+        assert_eq!(key_counts.total(&chords), 3);
+    }
+
+    #[test]
+    fn pairs() {
+        let chord_pairs = Mode::new(2, true);
+        let key_pairs = Mode::new(2, false);
+        let key_counts = run(shifted_a_then_b(), vec![chord_pairs, key_pairs]);
+
+        let pair = |first: &[Keycode], second: &[Keycode]| {
+            CountItem::Pair(first.to_vec(), second.to_vec())
+        };
+        assert_eq!(
+            key_counts.get(
+                &chord_pairs,
+                &pair(&[Keycode::LShift], &[Keycode::A, Keycode::LShift])
+            ),
+            1
+        );
+        assert_eq!(
+            key_counts.get(
+                &chord_pairs,
+                &pair(&[Keycode::A, Keycode::LShift], &[Keycode::B])
+            ),
+            1
+        );
+        assert_eq!(key_counts.total(&chord_pairs), 2);
+        assert_eq!(
+            key_counts.get(&key_pairs, &pair(&[Keycode::LShift], &[Keycode::A])),
+            1
+        );
+        assert_eq!(
+            key_counts.get(&key_pairs, &pair(&[Keycode::A], &[Keycode::B])),
+            1
+        );
+        assert_eq!(key_counts.total(&key_pairs), 2);
+    }
+
+    #[test]
+    fn polled_states() {
+        // keys seen down together by one poll make one chord
+        let source = ScriptedSource::from_states([
+            KeyState::new(vec![Keycode::LControl, Keycode::C], ms(0)),
+            KeyState::new(vec![Keycode::LControl], ms(100)),
+            KeyState::new(vec![Keycode::LControl, Keycode::V], ms(200)),
+            KeyState::new(vec![], ms(300)),
+            KeyState::new(vec![Keycode::V], ms(400)),
+        ]);
+        let chords = Mode::new(1, true);
+        let key_counts = run(source, vec![chords]);
+
+        assert_eq!(
+            key_counts.get(&chords, &single(&[Keycode::LControl, Keycode::C])),
+            1
+        );
+        assert_eq!(
+            key_counts.get(&chords, &single(&[Keycode::LControl, Keycode::V])),
+            1
+        );
+        assert_eq!(key_counts.get(&chords, &single(&[Keycode::V])), 1);
+        assert_eq!(key_counts.total(&chords), 3);
+    }
+
+    #[test]
+    fn dwell() {
+        let mut source = shifted_a_then_b();
+        let mut capture = Capture::new(KeyCounts::new(), vec![Mode::new(1, true)]);
+        capture.dwell = true;
+        while let Some(events) = source.poll() {
+            capture.update(events).unwrap();
+        }
+
+        let dwell = &capture.key_counts.dwell;
+        assert_eq!(dwell[&Keycode::LShift].count, 1);
+        assert_eq!(dwell[&Keycode::A].mean_ms, 10.0);
+    }
+
+    #[test]
+    fn events_going_back_in_time() {
+        let trace_path = TempFile::new("events-going-back-in-time.txt");
+        let mut capture = Capture::new(KeyCounts::new(), vec![Mode::new(1, true)]);
+        capture.trace_path = Some(trace_path.to_path_buf());
+        capture.dwell = true;
+
+        capture
+            .update(vec![
+                KeyEvent::press(Keycode::A, ms(100_000)),
+                KeyEvent::release(Keycode::A, ms(100_010)),
+                KeyEvent::press(Keycode::B, ms(50_000)),
+                KeyEvent::release(Keycode::B, ms(50_010)),
+            ])
+            .unwrap();
+
+        let chords = Mode::new(1, true);
+        assert_eq!(capture.key_counts.get(&chords, &single(&[Keycode::A])), 1);
+        assert_eq!(capture.key_counts.get(&chords, &single(&[Keycode::B])), 1);
+        assert_eq!(fs::read_to_string(&trace_path).unwrap(), "A\nB after 0ns\n");
+    }
+
+    #[test]
+    fn json_events_going_back_in_time() {
+        let trace_path = TempFile::new("json-events-going-back-in-time.jsonl");
+        let mut capture = Capture::new(KeyCounts::new(), vec![Mode::new(1, true)]);
+        capture.trace_path = Some(trace_path.to_path_buf());
+        capture.trace_format = TraceFormat::Json;
+
+        capture
+            .update(vec![
+                KeyEvent::press(Keycode::A, ms(100_000)),
+                KeyEvent::release(Keycode::A, ms(100_010)),
+                KeyEvent::press(Keycode::B, ms(50_000)),
+            ])
+            .unwrap();
+
+        let events = crate::trace::read_events(&trace_path).unwrap();
+        let times: Vec<(Duration, Duration)> = events
+            .iter()
+            .map(|event| (event.time, event.delta))
+            .collect();
+        assert_eq!(
+            times,
+            [
+                (Duration::ZERO, Duration::ZERO),
+                (ms(10), ms(10)),
+                (Duration::ZERO, Duration::ZERO)
+            ]
+        );
+    }
+
+    #[test]
+    fn json_wall_times_follow_event_timestamps() {
+        let trace_path = TempFile::new("json-wall-times.jsonl");
+        let mut capture = Capture::new(KeyCounts::new(), vec![Mode::new(1, true)]);
+        capture.trace_path = Some(trace_path.to_path_buf());
+        capture.trace_format = TraceFormat::Json;
+
+        // handled at once, though an hour apart
+        capture
+            .update(vec![
+                KeyEvent::press(Keycode::A, ms(5_000)),
+                KeyEvent::release(Keycode::A, ms(5_080)),
+                KeyEvent::press(Keycode::B, ms(3_605_000)),
+            ])
+            .unwrap();
+
+        let events = crate::trace::read_events(&trace_path).unwrap();
+        let walls: Vec<Duration> = events
+            .iter()
+            .map(|event| event.wall.duration_since(events[0].wall).unwrap())
+            .collect();
+        assert_eq!(walls, [Duration::ZERO, ms(80), ms(3_600_000)]);
+    }
+}
@@ -0,0 +1,1166 @@
+//! Statistics file: counts of [`CountItem`]s per [`Mode`] with timings,
+//! and the text form of keys used for it.
+
+use crate::binary;
+use crate::error::{self, Error, Setting};
+use crate::format::Format;
+use crate::persist;
+use crate::stats::DurationStats;
+use ahash::AHasher;
+use core::fmt;
+use device_query::Keycode;
+use serde::de::{self, MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::hash::BuildHasherDefault;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A [`HashMap`][hashbrown::HashMap] implementing aHash, a high
+/// speed keyed hashing algorithm intended for use in in-memory hashmaps.
+///
+/// aHash is designed for performance and is NOT cryptographically secure.
+///
+/// Within the same execution of the program iteration order of different
+/// `HashMap`s only depends on the order of insertions and deletions,
+/// but it will not be stable between multiple executions of the program.
+pub type HashMap<K, V> = hashbrown::HashMap<K, V, BuildHasherDefault<AHasher>>;
+
+pub const CHORDS_SECTION: &str = "chords";
+pub const KEYS_SECTION: &str = "keys";
+pub const CHORD_PAIRS_SECTION: &str = "chord-pairs";
+pub const KEY_PAIRS_SECTION: &str = "key-pairs";
+
+/// Content of a statistics file.
+///
+/// Serialized as a map with `config`, optional `dwell` and `flight` sections and
+/// counts keyed by [`count_item_to_string`], either right at the top level for a
+/// file with a single mode or in a section per [`Mode`] for a file with `collect` config.
+#[derive(Debug)]
+pub struct KeyCounts {
+    pub config: Option<Config>,
+    /// Counts per collected [`Mode`].
+    pub sections: BTreeMap<Mode, HashMap<CountItem, u32>>,
+    /// How long each key is held down, collected with `dwell` config.
+    pub dwell: HashMap<Keycode, DurationStats>,
+    /// Time between the inputs of each [`CountItem::Pair`] per pairs [`Mode`],
+    /// collected with `flight` config.
+    pub flight: BTreeMap<Mode, HashMap<CountItem, DurationStats>>,
+    /// Generation of the last [journal][crate::journal] already included, 0 for none.
+    pub journal: u64,
+    /// Order entries are serialized in, not saved in the file.
+    pub order: Order,
+}
+
+pub const KEY_ORDER: &str = "key";
+pub const COUNT_ORDER: &str = "count";
+
+/// Order entries of a statistics file are written in, so saving the same
+/// counts always gives the same file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    /// By [`count_item_to_string`], a save only changes lines of counts that changed.
+    #[default]
+    Key,
+    /// Most frequent first, ties by key.
+    Count,
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Order::Key => write!(f, "{KEY_ORDER}"),
+            Order::Count => write!(f, "{COUNT_ORDER}"),
+        }
+    }
+}
+
+impl FromStr for Order {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            KEY_ORDER => Ok(Order::Key),
+            COUNT_ORDER => Ok(Order::Count),
+            _ => Err(format!("{} is not a known order", s)),
+        }
+    }
+}
+
+impl KeyCounts {
+    pub fn new() -> Self {
+        Self {
+            config: None,
+            sections: BTreeMap::new(),
+            dwell: HashMap::new(),
+            flight: BTreeMap::new(),
+            journal: 0,
+            order: Order::default(),
+        }
+    }
+
+    /// Read a statistics file in the format told by its extension, YAML by default.
+    pub fn load(path: &Path) -> error::Result<Self> {
+        Self::load_as(path, Format::from_path(path).unwrap_or_default())
+    }
+
+    /// Read a statistics file in `format`.
+    pub fn load_as(path: &Path, format: Format) -> error::Result<Self> {
+        #[cfg(feature = "sqlite")]
+        if format == Format::Sqlite {
+            return crate::sqlite::Database::open(path)?.load();
+        }
+
+        let contents = fs::read(path).map_err(|error| Error::io(path, error))?;
+        Self::decode(&contents, format).map_err(|error| Error::parse(path, error))
+    }
+
+    pub fn from_yaml(s: &str) -> Result<Self, serde_yaml::Error> {
+        serde_yaml::from_str(s)
+    }
+
+    pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
+        serde_yaml::to_string(self)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(s)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Parse the contents of a statistics file in `format`, which must not be a database.
+    pub fn decode(contents: &[u8], format: Format) -> Result<Self, String> {
+        match format {
+            Format::Yaml => serde_yaml::from_slice(contents).map_err(|error| error.to_string()),
+            Format::Json => serde_json::from_slice(contents).map_err(|error| error.to_string()),
+            Format::Binary => binary::decode(contents),
+            #[cfg(feature = "sqlite")]
+            Format::Sqlite => Err("a database is not read from bytes".into()),
+        }
+    }
+
+    /// Contents of a statistics file in `format`, which must not be a database.
+    pub fn encode(&self, format: Format) -> Result<Vec<u8>, String> {
+        match format {
+            Format::Yaml => self
+                .to_yaml()
+                .map(String::into_bytes)
+                .map_err(|error| error.to_string()),
+            Format::Json => self
+                .to_json()
+                .map(String::into_bytes)
+                .map_err(|error| error.to_string()),
+            Format::Binary => Ok(binary::encode(self)),
+            #[cfg(feature = "sqlite")]
+            Format::Sqlite => Err("a database is not written as bytes".into()),
+        }
+    }
+
+    /// Write a statistics file in the format told by its extension, YAML by default,
+    /// atomically replacing `path`.
+    pub fn save(&self, path: &Path) -> error::Result<()> {
+        self.save_as(path, Format::from_path(path).unwrap_or_default())
+    }
+
+    /// Write a statistics file in `format`, atomically replacing `path`.
+    pub fn save_as(&self, path: &Path, format: Format) -> error::Result<()> {
+        #[cfg(feature = "sqlite")]
+        if format == Format::Sqlite {
+            return crate::sqlite::Database::open(path)?.save(self);
+        }
+
+        let serialized = self
+            .encode(format)
+            .map_err(|error| Error::io(path, io::Error::new(io::ErrorKind::InvalidData, error)))?;
+        persist::write_atomic(path, &serialized).map_err(|error| Error::io(path, error))
+    }
+
+    /// Counts of `mode`, `None` when it was not collected.
+    pub fn counts(&self, mode: &Mode) -> Option<&HashMap<CountItem, u32>> {
+        self.sections.get(mode)
+    }
+
+    pub fn counts_mut(&mut self, mode: Mode) -> &mut HashMap<CountItem, u32> {
+        self.sections.entry(mode).or_default()
+    }
+
+    /// How many times `count_item` was counted in `mode`.
+    pub fn get(&self, mode: &Mode, count_item: &CountItem) -> u32 {
+        self.counts(mode)
+            .and_then(|counts| counts.get(count_item))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Sum of all counts of `mode`.
+    pub fn total(&self, mode: &Mode) -> u64 {
+        self.counts(mode)
+            .map_or(0, |counts| counts.values().map(|count| *count as u64).sum())
+    }
+
+    /// Add counts and timings of `other` to `self`, section by section.
+    ///
+    /// Both must be collected with the same config, the version aside, see [`Config::check`];
+    /// files with different configs can be brought to a common one with [`KeyCounts::restrict`].
+    ///
+    /// Returns the items whose sum is too large for a count, they are kept at [`u32::MAX`].
+    pub fn merge(&mut self, other: KeyCounts) -> error::Result<Vec<(Mode, CountItem)>> {
+        let config_of = |key_counts: &KeyCounts| key_counts.config.clone().unwrap_or_default();
+        config_of(&other).check(&config_of(self))?;
+
+        let mut overflowed = Vec::new();
+        for (mode, counts) in other.sections {
+            let section = self.counts_mut(mode);
+            for (count_item, count) in counts {
+                let sum = section.entry(count_item.clone()).or_insert(0);
+                *sum = sum.checked_add(count).unwrap_or_else(|| {
+                    overflowed.push((mode, count_item));
+                    u32::MAX
+                });
+            }
+        }
+
+        for (key, stats) in other.dwell {
+            self.dwell.entry(key).or_default().merge(&stats);
+        }
+
+        for (mode, flight) in other.flight {
+            let section = self.flight.entry(mode).or_default();
+            for (count_item, stats) in flight {
+                section.entry(count_item).or_default().merge(&stats);
+            }
+        }
+
+        Ok(overflowed)
+    }
+
+    /// Drop everything `config` does not count and take it as the config,
+    /// `config` being one that the current config can be brought to, see [`Config::common`].
+    pub fn restrict(&mut self, config: Config) {
+        let modes = config.modes();
+        self.sections.retain(|mode, _| modes.contains(mode));
+        if config.flight {
+            self.flight.retain(|mode, _| modes.contains(mode));
+        } else {
+            self.flight.clear();
+        }
+        if !config.dwell {
+            self.dwell.clear();
+        }
+
+        self.config = Some(config);
+    }
+
+    /// Give `config` to a file that had none, counts read from it
+    /// are taken as counts of the first mode of `config`.
+    pub fn set_config(&mut self, config: Config) {
+        if self.config.is_none() {
+            let mode = config.modes()[0];
+            if let Some(counts) = self.sections.remove(&Config::default().modes()[0]) {
+                self.sections.insert(mode, counts);
+            }
+            if let Some(flight) = self.flight.remove(&Config::default().modes()[0]) {
+                self.flight.insert(mode, flight);
+            }
+        }
+
+        self.config = Some(config);
+    }
+
+    /// Files without `collect` config keep the layout of a single mode:
+    /// counts right at the top level next to `config`, unless they hold
+    /// counts of another mode, which would be read as counts of the config mode.
+    fn is_sectioned(&self) -> bool {
+        let config = match self.config {
+            Some(ref config) if !config.collect.is_empty() => return true,
+            Some(ref config) => config.modes()[0],
+            None => Config::default().modes()[0],
+        };
+
+        self.sections
+            .keys()
+            .chain(self.flight.keys())
+            .any(|mode| *mode != config)
+    }
+}
+
+impl Default for KeyCounts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What one section of a statistics file counts:
+/// `ngram` inputs in a row, where an input is either a whole chord
+/// (all keys held together) or a single key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Mode {
+    pub ngram: usize,
+    pub chords: bool,
+}
+
+impl Mode {
+    pub fn new(ngram: usize, chords: bool) -> Self {
+        Self { ngram, chords }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.ngram, self.chords) {
+            (1, true) => write!(f, "{CHORDS_SECTION}"),
+            (1, false) => write!(f, "{KEYS_SECTION}"),
+            (2, true) => write!(f, "{CHORD_PAIRS_SECTION}"),
+            (2, false) => write!(f, "{KEY_PAIRS_SECTION}"),
+            (ngram, true) => write!(f, "chord-{ngram}grams"),
+            (ngram, false) => write!(f, "key-{ngram}grams"),
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            CHORDS_SECTION => return Ok(Mode::new(1, true)),
+            KEYS_SECTION => return Ok(Mode::new(1, false)),
+            CHORD_PAIRS_SECTION => return Ok(Mode::new(2, true)),
+            KEY_PAIRS_SECTION => return Ok(Mode::new(2, false)),
+            _ => {}
+        }
+
+        let (chords, ngram) = if let Some(ngram) = s.strip_prefix("chord-") {
+            (true, ngram)
+        } else if let Some(ngram) = s.strip_prefix("key-") {
+            (false, ngram)
+        } else {
+            return Err(format!("unrecognized section {s}"));
+        };
+
+        match ngram.strip_suffix("grams").map(str::parse::<usize>) {
+            Some(Ok(ngram)) if ngram > 0 => Ok(Mode::new(ngram, chords)),
+            _ => Err(format!("unrecognized section {s}")),
+        }
+    }
+}
+
+impl Serialize for Mode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Mode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Options a statistics file was collected with.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Config {
+    pub pairs: bool,
+    /// Length of counted n-grams when it is 3 or more,
+    /// singles and pairs are told apart by `pairs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ngram: Option<usize>,
+    pub no_chords: bool,
+    /// Modes collected in one run, each in its own section;
+    /// when empty the only mode is given by `pairs`, `ngram` and `no_chords`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub collect: Vec<Mode>,
+    #[serde(default)]
+    pub dwell: bool,
+    #[serde(default)]
+    pub flight: bool,
+    pub version: String,
+}
+
+impl Config {
+    pub fn new(ngram: usize, no_chords: bool, dwell: bool, flight: bool, version: String) -> Self {
+        Self {
+            pairs: ngram == 2,
+            ngram: (ngram > 2).then_some(ngram),
+            no_chords,
+            collect: Vec::new(),
+            dwell,
+            flight,
+            version,
+        }
+    }
+
+    /// Config of a file collecting several `modes` at once.
+    pub fn collecting(mut modes: Vec<Mode>, dwell: bool, flight: bool, version: String) -> Self {
+        modes.sort();
+        modes.dedup();
+
+        Self {
+            collect: modes,
+            ..Self::new(1, false, dwell, flight, version)
+        }
+    }
+
+    /// How many inputs make one counted item, for files without `collect`.
+    pub fn ngram(&self) -> usize {
+        match self.ngram {
+            Some(ngram) => ngram,
+            None if self.pairs => 2,
+            None => 1,
+        }
+    }
+
+    /// Every mode the file counts, in the order of its sections.
+    pub fn modes(&self) -> Vec<Mode> {
+        if self.collect.is_empty() {
+            vec![Mode::new(self.ngram(), !self.no_chords)]
+        } else {
+            self.collect.clone()
+        }
+    }
+
+    /// Config counting only what both `self` and `other` count:
+    /// their common modes, and dwell or flight times when both have them.
+    /// `None` when they have no mode in common. The version is the one of `self`.
+    pub fn common(&self, other: &Config) -> Option<Config> {
+        let other_modes = other.modes();
+        let modes: Vec<Mode> = self
+            .modes()
+            .into_iter()
+            .filter(|mode| other_modes.contains(mode))
+            .collect();
+        let dwell = self.dwell && other.dwell;
+        let flight = self.flight && other.flight;
+
+        match modes.as_slice() {
+            [] => None,
+            // a single mode keeps the layout without sections
+            [mode] if self.collect.is_empty() || other.collect.is_empty() => Some(Config::new(
+                mode.ngram,
+                !mode.chords,
+                dwell,
+                flight,
+                self.version.clone(),
+            )),
+            _ => Some(Config::collecting(
+                modes,
+                dwell,
+                flight,
+                self.version.clone(),
+            )),
+        }
+    }
+
+    /// Check that a file with `self` config can go on with `options`,
+    /// the version is not compared.
+    pub fn check(&self, options: &Config) -> error::Result<()> {
+        let mismatch = |setting, options: String, file: String| {
+            Err(Error::ConfigMismatch {
+                setting,
+                options,
+                file,
+            })
+        };
+
+        if !self.collect.is_empty() || !options.collect.is_empty() {
+            if self.modes() != options.modes() {
+                let modes = |config: &Config| {
+                    config
+                        .modes()
+                        .iter()
+                        .map(Mode::to_string)
+                        .collect::<Vec<String>>()
+                        .join(",")
+                };
+                return mismatch(Setting::Collect, modes(options), modes(self));
+            }
+        } else if self.ngram() != options.ngram() {
+            return mismatch(
+                Setting::Ngram,
+                options.ngram().to_string(),
+                self.ngram().to_string(),
+            );
+        }
+
+        if self.no_chords != options.no_chords {
+            return mismatch(
+                Setting::NoChords,
+                options.no_chords.to_string(),
+                self.no_chords.to_string(),
+            );
+        }
+
+        if self.dwell != options.dwell {
+            return mismatch(
+                Setting::Dwell,
+                options.dwell.to_string(),
+                self.dwell.to_string(),
+            );
+        }
+
+        if self.flight != options.flight {
+            return mismatch(
+                Setting::Flight,
+                options.flight.to_string(),
+                self.flight.to_string(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// What is counted: one input or several inputs one after another,
+/// where an input is the keys held together.
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum CountItem {
+    Single(Vec<Keycode>),
+    Pair(Vec<Keycode>, Vec<Keycode>),
+    /// Three or more inputs one after another.
+    Ngram(Vec<Vec<Keycode>>),
+}
+
+impl CountItem {
+    /// Inputs in the order they were pressed.
+    pub fn inputs(&self) -> Vec<&[Keycode]> {
+        match self {
+            CountItem::Single(input) => vec![input],
+            CountItem::Pair(first, second) => vec![first, second],
+            CountItem::Ngram(inputs) => inputs.iter().map(Vec::as_slice).collect(),
+        }
+    }
+}
+
+pub fn keycode_to_string(keycode: &Keycode) -> String {
+    format!("{:?}", keycode)
+}
+
+/// Keys of one input joined with `+`, e.g. `A+LShift`.
+pub fn input_to_string(input: &[Keycode]) -> String {
+    let keys_str: Vec<String> = input.iter().map(keycode_to_string).collect();
+    format!("{:?}", keys_str.join("+")).replace('"', "")
+}
+
+/// Inputs joined with `, `, e.g. `A+LShift, B`; the reverse of [`parse_count_item`].
+pub fn count_item_to_string(count_item: &CountItem) -> String {
+    match count_item {
+        CountItem::Single(input) => input_to_string(input),
+        CountItem::Pair(input1, input2) => {
+            let input1_str: String = input_to_string(input1);
+            let input2_str: String = input_to_string(input2);
+            format!("{}, {}", input1_str, input2_str)
+        }
+        CountItem::Ngram(inputs) => inputs
+            .iter()
+            .map(|input| input_to_string(input))
+            .collect::<Vec<String>>()
+            .join(", "),
+    }
+}
+
+/// Entries serialized as a map in the order they are in.
+struct Ordered<V>(Vec<(String, V)>);
+
+impl<V: Serialize> Serialize for Ordered<V> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<V> Ordered<V> {
+    /// `entries` in `order`, counted by `count`.
+    fn new(
+        entries: impl Iterator<Item = (String, V)>,
+        order: Order,
+        count: impl Fn(&V) -> u64,
+    ) -> Self {
+        let mut entries: Vec<(String, V)> = entries.collect();
+        match order {
+            Order::Key => entries.sort_by(|(a, _), (b, _)| a.cmp(b)),
+            Order::Count => entries.sort_by(|(a_key, a), (b_key, b)| {
+                count(b).cmp(&count(a)).then_with(|| a_key.cmp(b_key))
+            }),
+        }
+        Self(entries)
+    }
+}
+
+fn flight_to_strings(
+    flight: &HashMap<CountItem, DurationStats>,
+    order: Order,
+) -> Ordered<&DurationStats> {
+    Ordered::new(
+        flight
+            .iter()
+            .map(|(key, stats)| (count_item_to_string(key), stats)),
+        order,
+        |stats| stats.count,
+    )
+}
+
+fn counts_to_strings(counts: &HashMap<CountItem, u32>, order: Order) -> Ordered<u32> {
+    Ordered::new(
+        counts
+            .iter()
+            .map(|(key, value)| (count_item_to_string(key), *value)),
+        order,
+        |count| *count as u64,
+    )
+}
+
+impl Serialize for KeyCounts {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+
+        map.serialize_entry("config", &self.config)?;
+
+        if self.journal != 0 {
+            map.serialize_entry("journal", &self.journal)?;
+        }
+
+        if !self.dwell.is_empty() {
+            let dwell = Ordered::new(
+                self.dwell
+                    .iter()
+                    .map(|(key, stats)| (keycode_to_string(key), stats)),
+                self.order,
+                |stats| stats.count,
+            );
+            map.serialize_entry("dwell", &dwell)?;
+        }
+
+        if self.is_sectioned() {
+            if !self.flight.is_empty() {
+                let flight: BTreeMap<String, Ordered<&DurationStats>> = self
+                    .flight
+                    .iter()
+                    .map(|(mode, flight)| (mode.to_string(), flight_to_strings(flight, self.order)))
+                    .collect();
+                map.serialize_entry("flight", &flight)?;
+            }
+
+            for (mode, counts) in &self.sections {
+                map.serialize_entry(&mode.to_string(), &counts_to_strings(counts, self.order))?;
+            }
+        } else {
+            if let Some(flight) = self.flight.values().next() {
+                map.serialize_entry("flight", &flight_to_strings(flight, self.order))?;
+            }
+
+            for counts in self.sections.values() {
+                for (key, value) in counts_to_strings(counts, self.order).0 {
+                    map.serialize_entry(&key, &value)?;
+                }
+            }
+        }
+
+        map.end()
+    }
+}
+
+pub fn parse_keycode_from_string(s: &str) -> Result<Keycode, String> {
+    log::trace!("try to parse keycode from {}", s);
+    Keycode::from_str(s)
+}
+
+/// The reverse of [`input_to_string`].
+pub fn parse_input_from_string(s: &str) -> Result<Vec<Keycode>, String> {
+    let keycodes = s
+        .split('+')
+        .filter_map(|s| {
+            let trimmed = s.trim();
+            if trimmed.is_empty() {
+                None
+            } else {
+                Some(parse_keycode_from_string(trimmed))
+            }
+        })
+        .collect::<Result<Vec<Keycode>, String>>()?;
+
+    Ok(keycodes)
+}
+
+/// Count of `key_str`, refused when it is too large for a count.
+fn parse_count<E: de::Error>(key_str: &str, value: u64) -> Result<u32, E> {
+    u32::try_from(value).map_err(|_| {
+        E::custom(format!(
+            "count {value} of {key_str} is larger than {}",
+            u32::MAX
+        ))
+    })
+}
+
+fn parse_counts<E: de::Error>(value: serde_yaml::Value) -> Result<HashMap<CountItem, u32>, E> {
+    let counts: HashMap<String, u64> = serde_yaml::from_value(value).map_err(E::custom)?;
+    counts
+        .into_iter()
+        .map(|(key_str, value)| {
+            Ok((
+                parse_count_item(&key_str).map_err(E::custom)?,
+                parse_count(&key_str, value)?,
+            ))
+        })
+        .collect()
+}
+
+fn parse_flight<E: de::Error>(
+    value: serde_yaml::Value,
+) -> Result<HashMap<CountItem, DurationStats>, E> {
+    let stats: BTreeMap<String, DurationStats> =
+        serde_yaml::from_value(value).map_err(E::custom)?;
+    stats
+        .into_iter()
+        .map(|(key_str, stats)| Ok((parse_count_item(&key_str).map_err(E::custom)?, stats)))
+        .collect()
+}
+
+/// Whether every key of `value` is a section name.
+fn is_keyed_by_mode(value: &serde_yaml::Value) -> bool {
+    value.as_mapping().map_or(false, |mapping| {
+        mapping.keys().all(|key| {
+            key.as_str()
+                .map_or(false, |key| key.parse::<Mode>().is_ok())
+        })
+    })
+}
+
+/// The reverse of [`count_item_to_string`].
+pub fn parse_count_item(s: &str) -> Result<CountItem, String> {
+    let inputs = s
+        .split(", ")
+        .map(parse_input_from_string)
+        .collect::<Result<Vec<Vec<Keycode>>, String>>()?;
+
+    match inputs.len() {
+        1 => Ok(CountItem::Single(inputs[0].clone())),
+        2 => Ok(CountItem::Pair(inputs[0].clone(), inputs[1].clone())),
+        _ => Ok(CountItem::Ngram(inputs)),
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyCounts {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct KeyCountsVisitor;
+
+        impl<'de> Visitor<'de> for KeyCountsVisitor {
+            type Value = KeyCounts;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map of CountItem to u32")
+            }
+
+            fn visit_map<M>(self, mut map: M) -> Result<KeyCounts, M::Error>
+            where
+                M: MapAccess<'de>,
+            {
+                let mut counts = HashMap::new();
+                let mut sections = BTreeMap::new();
+                let mut config: Option<Config> = None;
+                let mut dwell = HashMap::new();
+                let mut flight = None;
+                let mut journal = 0;
+
+                while let Some((key_str, value)) = map.next_entry::<String, serde_yaml::Value>()? {
+                    match key_str.as_str() {
+                        // `null` for counts saved without config
+                        "config" => {
+                            config = serde_yaml::from_value(value).map_err(de::Error::custom)?;
+                        }
+                        "dwell" => {
+                            let stats: BTreeMap<String, DurationStats> =
+                                serde_yaml::from_value(value).map_err(de::Error::custom)?;
+                            for (key_str, stats) in stats {
+                                let key = parse_keycode_from_string(&key_str)
+                                    .map_err(de::Error::custom)?;
+                                dwell.insert(key, stats);
+                            }
+                        }
+                        "journal" => {
+                            journal = serde_yaml::from_value(value).map_err(de::Error::custom)?;
+                        }
+                        // layout depends on config, which may come later
+                        "flight" => flight = Some(value),
+                        _ => match key_str.parse::<Mode>() {
+                            Ok(mode) if value.is_mapping() => {
+                                sections.insert(mode, parse_counts(value)?);
+                            }
+                            _ => {
+                                let count_item =
+                                    parse_count_item(&key_str).map_err(de::Error::custom)?;
+                                let value = value
+                                    .as_u64()
+                                    .ok_or_else(|| de::Error::custom("Expected u64 value"))?;
+                                counts.insert(count_item, parse_count(&key_str, value)?);
+                            }
+                        },
+                    }
+                }
+
+                // a file without config is read as the default single mode
+                let collect = config
+                    .as_ref()
+                    .map_or(false, |config| !config.collect.is_empty());
+                let mode = config.clone().unwrap_or_default().modes()[0];
+                if !collect {
+                    sections.entry(mode).or_default().extend(counts);
+                }
+
+                // a single mode file has flight sections too when it holds other modes
+                let flight = match flight {
+                    Some(value) if is_keyed_by_mode(&value) => {
+                        let flight: BTreeMap<Mode, serde_yaml::Value> =
+                            serde_yaml::from_value(value).map_err(de::Error::custom)?;
+                        flight
+                            .into_iter()
+                            .map(|(mode, value)| Ok((mode, parse_flight(value)?)))
+                            .collect::<Result<_, M::Error>>()?
+                    }
+                    Some(value) => BTreeMap::from([(mode, parse_flight(value)?)]),
+                    None => BTreeMap::new(),
+                };
+
+                Ok(KeyCounts {
+                    config,
+                    sections,
+                    dwell,
+                    flight,
+                    journal,
+                    order: Order::default(),
+                })
+            }
+        }
+        deserializer.deserialize_map(KeyCountsVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_keeps_too_large_counts_at_max() {
+        let mut key_counts = KeyCounts::from_yaml("A: 4000000000\nB: 1\n").unwrap();
+        let other = KeyCounts::from_yaml("A: 4000000000\nB: 2\n").unwrap();
+
+        let chords = Mode::new(1, true);
+        let a = CountItem::Single(vec![Keycode::A]);
+        assert_eq!(key_counts.merge(other).unwrap(), [(chords, a.clone())]);
+        assert_eq!(key_counts.get(&chords, &a), u32::MAX);
+        assert_eq!(
+            key_counts.get(&chords, &CountItem::Single(vec![Keycode::B])),
+            3
+        );
+    }
+
+    fn version() -> String {
+        "1.1.0".into()
+    }
+
+    #[test]
+    fn merge_refuses_other_config() {
+        let mut key_counts = KeyCounts::from_yaml("A: 3\n").unwrap();
+        let pairs = KeyCounts::from_yaml(
+            "config:\n  pairs: true\n  no_chords: false\n  version: 1.1.0\nA, B: 5\n",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            key_counts.merge(pairs),
+            Err(Error::ConfigMismatch {
+                setting: Setting::Ngram,
+                ..
+            })
+        ));
+        assert_eq!(key_counts.sections.len(), 1);
+        assert_eq!(key_counts.total(&Mode::new(1, true)), 3);
+    }
+
+    #[test]
+    fn check_configs() {
+        let config = Config::new(2, false, true, false, version());
+        let mut other = config.clone();
+        other.version = "0.1.0".into();
+        assert!(other.check(&config).is_ok());
+
+        let collecting = Config::collecting(
+            vec![Mode::new(1, true), Mode::new(2, true)],
+            true,
+            false,
+            version(),
+        );
+        assert!(matches!(
+            collecting.check(&config),
+            Err(Error::ConfigMismatch {
+                setting: Setting::Collect,
+                ..
+            })
+        ));
+
+        let no_dwell = Config::new(2, false, false, false, version());
+        assert!(matches!(
+            no_dwell.check(&config),
+            Err(Error::ConfigMismatch {
+                setting: Setting::Dwell,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn common_configs() {
+        let chords = Mode::new(1, true);
+        let pairs = Mode::new(2, true);
+        let keys = Mode::new(1, false);
+        let singles = Config::new(1, false, true, true, version());
+        let collecting = Config::collecting(vec![chords, pairs, keys], true, false, version());
+
+        let common = singles.common(&collecting).unwrap();
+        assert!(common.collect.is_empty());
+        assert_eq!(common.modes(), [chords]);
+        assert!(common.dwell);
+        assert!(!common.flight);
+
+        let other = Config::collecting(vec![pairs, keys], false, false, version());
+        let common = collecting.common(&other).unwrap();
+        assert_eq!(common.collect, [keys, pairs]);
+        assert!(!common.dwell);
+
+        let no_chords = Config::new(1, true, true, true, version());
+        assert!(singles.common(&no_chords).is_none());
+    }
+
+    #[test]
+    fn converted_merge() {
+        let chords = Mode::new(1, true);
+        let pairs = Mode::new(2, true);
+        let a = CountItem::Single(vec![Keycode::A]);
+        let mut collected = KeyCounts::new();
+        collected.set_config(Config::collecting(
+            vec![chords, pairs],
+            true,
+            true,
+            version(),
+        ));
+        collected.counts_mut(chords).insert(a.clone(), 2);
+        collected
+            .counts_mut(pairs)
+            .insert(CountItem::Pair(vec![Keycode::A], vec![Keycode::B]), 1);
+        collected.dwell.insert(Keycode::A, DurationStats::default());
+        let mut singles = KeyCounts::from_yaml("A: 3\n").unwrap();
+        singles.set_config(Config::default());
+
+        let common = collected
+            .config
+            .as_ref()
+            .unwrap()
+            .common(singles.config.as_ref().unwrap())
+            .unwrap();
+        collected.restrict(common.clone());
+        singles.restrict(common);
+        assert!(collected.merge(singles).unwrap().is_empty());
+
+        assert_eq!(collected.sections.keys().collect::<Vec<_>>(), [&chords]);
+        assert_eq!(collected.get(&chords, &a), 5);
+        assert!(collected.dwell.is_empty());
+        assert!(collected.flight.is_empty());
+        assert!(collected.config.unwrap().collect.is_empty());
+    }
+
+    #[test]
+    fn too_large_counts_are_refused() {
+        let error = KeyCounts::from_yaml("A: 5000000000\n").unwrap_err();
+        assert!(error.to_string().contains("count 5000000000 of A"));
+
+        let error = KeyCounts::from_json(r#"{"A": 4294967296}"#).unwrap_err();
+        assert!(error.to_string().contains("4294967296"));
+
+        let sectioned =
+            "config:\n  pairs: false\n  no_chords: false\n  collect: [keys]\n  version: 1.1.0\n";
+        assert!(KeyCounts::from_yaml(&format!("{sectioned}keys:\n  A: 5000000000\n")).is_err());
+        let key_counts =
+            KeyCounts::from_yaml(&format!("{sectioned}keys:\n  A: 4294967295\n")).unwrap();
+        assert_eq!(
+            key_counts.get(&Mode::new(1, false), &CountItem::Single(vec![Keycode::A])),
+            u32::MAX
+        );
+    }
+
+    #[test]
+    fn sections() {
+        let yaml = "config:
+  pairs: false
+  no_chords: false
+  collect: [chords, key-pairs, chord-3grams]
+  version: 1.1.0
+chords:
+  A+LShift: 2
+key-pairs:
+  A, B: 1
+chord-3grams:
+  A, B, C+LControl: 4
+";
+        let key_counts = KeyCounts::from_yaml(yaml).unwrap();
+
+        assert_eq!(
+            key_counts.get(
+                &Mode::new(1, true),
+                &CountItem::Single(vec![Keycode::A, Keycode::LShift])
+            ),
+            2
+        );
+        assert_eq!(
+            key_counts.get(
+                &Mode::new(2, false),
+                &CountItem::Pair(vec![Keycode::A], vec![Keycode::B])
+            ),
+            1
+        );
+        assert_eq!(
+            key_counts.get(
+                &Mode::new(3, true),
+                &CountItem::Ngram(vec![
+                    vec![Keycode::A],
+                    vec![Keycode::B],
+                    vec![Keycode::C, Keycode::LControl]
+                ])
+            ),
+            4
+        );
+        assert!(KeyCounts::from_yaml(&yaml.replace("chords:\n  A+", "chords:\n  Nope+")).is_err());
+    }
+
+    #[test]
+    fn modes() {
+        for (name, mode) in [
+            ("chords", Mode::new(1, true)),
+            ("keys", Mode::new(1, false)),
+            ("chord-pairs", Mode::new(2, true)),
+            ("key-pairs", Mode::new(2, false)),
+            ("chord-3grams", Mode::new(3, true)),
+            ("key-12grams", Mode::new(12, false)),
+        ] {
+            assert_eq!(name.parse(), Ok(mode));
+            assert_eq!(mode.to_string(), name);
+        }
+
+        for name in ["chord-0grams", "key-grams", "chord-3gram", "pairs", "A"] {
+            assert!(name.parse::<Mode>().is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn other_modes_of_single_mode_file_round_trip() {
+        let pairs = Mode::new(2, true);
+        let chords = Mode::new(1, true);
+        let a_b = CountItem::Pair(vec![Keycode::A], vec![Keycode::B]);
+        let a = CountItem::Single(vec![Keycode::A]);
+        let mut key_counts = KeyCounts::from_yaml(
+            "config:\n  pairs: true\n  no_chords: false\n  flight: true\n  version: 1.1.0\nA, B: 5\n",
+        )
+        .unwrap();
+        key_counts.counts_mut(chords).insert(a.clone(), 3);
+        key_counts
+            .flight
+            .entry(chords)
+            .or_default()
+            .insert(a_b.clone(), DurationStats::default());
+
+        let yaml = key_counts.to_yaml().unwrap();
+        let key_counts = KeyCounts::from_yaml(&yaml).unwrap();
+
+        assert_eq!(key_counts.get(&pairs, &a_b), 5);
+        assert_eq!(key_counts.get(&chords, &a), 3);
+        assert_eq!(key_counts.flight.keys().collect::<Vec<_>>(), [&chords]);
+        assert_eq!(key_counts.to_yaml().unwrap(), yaml);
+    }
+
+    #[test]
+    fn single_mode_file_stays_flat() {
+        let yaml = "config:\n  pairs: true\n  no_chords: false\n  dwell: false\n  flight: false\n  version: 1.1.0\nA, B: 5\n";
+        let key_counts = KeyCounts::from_yaml(yaml).unwrap();
+
+        assert_eq!(key_counts.to_yaml().unwrap(), yaml);
+    }
+
+    /// Counts A: 7, B: 1, C: 2 and D: 2 inserted in the order of `keys`.
+    fn inserted(keys: &[Keycode]) -> KeyCounts {
+        let mut key_counts = KeyCounts::new();
+        for key in keys {
+            let count = match key {
+                Keycode::A => 7,
+                Keycode::B => 1,
+                _ => 2,
+            };
+            key_counts
+                .counts_mut(Mode::new(1, true))
+                .insert(CountItem::Single(vec![*key]), count);
+        }
+        key_counts
+    }
+
+    #[test]
+    fn key_order_does_not_depend_on_insertion() {
+        let first = inserted(&[Keycode::C, Keycode::A, Keycode::D, Keycode::B]);
+        let second = inserted(&[Keycode::B, Keycode::D, Keycode::A, Keycode::C]);
+
+        let yaml = first.to_yaml().unwrap();
+        assert_eq!(yaml, "config: null\nA: 7\nB: 1\nC: 2\nD: 2\n");
+        assert_eq!(second.to_yaml().unwrap(), yaml);
+
+        let loaded = KeyCounts::from_yaml(&yaml).unwrap();
+        assert!(loaded.config.is_none());
+        assert_eq!(loaded.total(&Mode::new(1, true)), 12);
+        let json = first.to_json().unwrap();
+        assert_eq!(
+            KeyCounts::from_json(&json).unwrap().to_json().unwrap(),
+            json
+        );
+    }
+
+    #[test]
+    fn count_order_breaks_ties_by_key() {
+        let mut key_counts = inserted(&[Keycode::D, Keycode::A, Keycode::C, Keycode::B]);
+        key_counts.order = Order::Count;
+
+        assert_eq!(
+            key_counts.to_yaml().unwrap(),
+            "config: null\nA: 7\nC: 2\nD: 2\nB: 1\n"
+        );
+    }
+
+    #[test]
+    fn orders() {
+        for order in [Order::Key, Order::Count] {
+            assert_eq!(order.to_string().parse(), Ok(order));
+        }
+        assert!("size".parse::<Order>().is_err());
+    }
+}
@@ -0,0 +1,294 @@
+//! Comparison of two statistics files, section by section.
+//!
+//! Counts of files collected over different periods are compared by their
+//! share of the section total, so a longer period alone does not move anything.
+
+use crate::counts::{count_item_to_string, CountItem, HashMap, KeyCounts, Mode};
+use serde_derive::Serialize;
+use std::cmp::Ordering;
+
+/// How one item changed between the two files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub item: CountItem,
+    pub before: u32,
+    pub after: u32,
+    /// Share of the section total, from 0 to 1.
+    pub share_before: f64,
+    pub share_after: f64,
+}
+
+impl Change {
+    pub fn delta(&self) -> i64 {
+        self.after as i64 - self.before as i64
+    }
+
+    /// Change of the share, in percentage points.
+    pub fn share_change(&self) -> f64 {
+        (self.share_after - self.share_before) * 100.0
+    }
+
+    /// Change of the share relative to the share before, `None` for added items.
+    pub fn relative(&self) -> Option<f64> {
+        (self.share_before > 0.0).then_some(self.share_after / self.share_before - 1.0)
+    }
+
+    pub fn is_added(&self) -> bool {
+        self.before == 0
+    }
+
+    pub fn is_removed(&self) -> bool {
+        self.after == 0
+    }
+}
+
+/// Changes of one section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionDiff {
+    pub mode: Mode,
+    pub total_before: u64,
+    pub total_after: u64,
+    /// Every item counted in either file, the most moved share first.
+    pub changes: Vec<Change>,
+}
+
+impl SectionDiff {
+    pub fn new(
+        mode: Mode,
+        before: Option<&HashMap<CountItem, u32>>,
+        after: Option<&HashMap<CountItem, u32>>,
+    ) -> Self {
+        let total = |counts: Option<&HashMap<CountItem, u32>>| {
+            counts.map_or(0, |counts| counts.values().map(|count| *count as u64).sum())
+        };
+        let total_before = total(before);
+        let total_after = total(after);
+        let share = |count: u32, total: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                count as f64 / total as f64
+            }
+        };
+
+        let mut counts: HashMap<&CountItem, (u32, u32)> = HashMap::new();
+        for (item, count) in before.into_iter().flatten() {
+            counts.entry(item).or_default().0 = *count;
+        }
+        for (item, count) in after.into_iter().flatten() {
+            counts.entry(item).or_default().1 = *count;
+        }
+
+        let mut changes: Vec<Change> = counts
+            .into_iter()
+            .map(|(item, (before, after))| Change {
+                item: item.clone(),
+                before,
+                after,
+                share_before: share(before, total_before),
+                share_after: share(after, total_after),
+            })
+            .collect();
+        changes.sort_by_cached_key(|change| count_item_to_string(&change.item));
+        changes.sort_by(|a, b| {
+            b.share_change()
+                .abs()
+                .partial_cmp(&a.share_change().abs())
+                .unwrap_or(Ordering::Equal)
+        });
+
+        Self {
+            mode,
+            total_before,
+            total_after,
+            changes,
+        }
+    }
+
+    pub fn added(&self) -> impl Iterator<Item = &Change> {
+        self.changes.iter().filter(|change| change.is_added())
+    }
+
+    pub fn removed(&self) -> impl Iterator<Item = &Change> {
+        self.changes.iter().filter(|change| change.is_removed())
+    }
+
+    /// Up to `n` items whose share changed the most.
+    pub fn top_movers(&self, n: usize) -> impl Iterator<Item = &Change> {
+        self.changes
+            .iter()
+            .filter(|change| change.share_change() != 0.0)
+            .take(n)
+    }
+}
+
+/// Changes from `before` to `after` in every section found in either of them.
+pub fn diff(before: &KeyCounts, after: &KeyCounts) -> Vec<SectionDiff> {
+    let mut modes: Vec<Mode> = before
+        .sections
+        .keys()
+        .chain(after.sections.keys())
+        .copied()
+        .collect();
+    modes.sort();
+    modes.dedup();
+
+    modes
+        .into_iter()
+        .map(|mode| SectionDiff::new(mode, before.counts(&mode), after.counts(&mode)))
+        .collect()
+}
+
+#[derive(Serialize)]
+struct JsonSection {
+    section: String,
+    total_before: u64,
+    total_after: u64,
+    added: Vec<String>,
+    removed: Vec<String>,
+    top_movers: Vec<String>,
+    changes: Vec<JsonChange>,
+}
+
+#[derive(Serialize)]
+struct JsonChange {
+    item: String,
+    before: u32,
+    after: u32,
+    delta: i64,
+    share_before: f64,
+    share_after: f64,
+    share_change: f64,
+    relative: Option<f64>,
+}
+
+/// `sections` as pretty printed JSON, with up to `top` movers each.
+pub fn to_json(sections: &[SectionDiff], top: usize) -> String {
+    let names = |changes: &mut dyn Iterator<Item = &Change>| {
+        changes
+            .map(|change| count_item_to_string(&change.item))
+            .collect()
+    };
+
+    let sections: Vec<JsonSection> = sections
+        .iter()
+        .map(|section| JsonSection {
+            section: section.mode.to_string(),
+            total_before: section.total_before,
+            total_after: section.total_after,
+            added: names(&mut section.added()),
+            removed: names(&mut section.removed()),
+            top_movers: names(&mut section.top_movers(top)),
+            changes: section
+                .changes
+                .iter()
+                .map(|change| JsonChange {
+                    item: count_item_to_string(&change.item),
+                    before: change.before,
+                    after: change.after,
+                    delta: change.delta(),
+                    share_before: change.share_before,
+                    share_after: change.share_after,
+                    share_change: change.share_change(),
+                    relative: change.relative(),
+                })
+                .collect(),
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&sections).expect("diff is always serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device_query::Keycode;
+
+    fn single(key: Keycode) -> CountItem {
+        CountItem::Single(vec![key])
+    }
+
+    fn change(section: &SectionDiff, key: Keycode) -> &Change {
+        section
+            .changes
+            .iter()
+            .find(|change| change.item == single(key))
+            .unwrap()
+    }
+
+    fn keys<'a>(changes: impl Iterator<Item = &'a Change>) -> Vec<CountItem> {
+        changes.map(|change| change.item.clone()).collect()
+    }
+
+    #[test]
+    fn added_removed_and_changed() {
+        let before = KeyCounts::from_yaml("A: 4\nB: 2\nC: 2\n").unwrap();
+        let after = KeyCounts::from_yaml("A: 8\nB: 6\nD: 2\n").unwrap();
+
+        let sections = diff(&before, &after);
+        assert_eq!(sections.len(), 1);
+        let section = &sections[0];
+        assert_eq!(section.mode, Mode::new(1, true));
+        assert_eq!((section.total_before, section.total_after), (8, 16));
+
+        assert_eq!(keys(section.added()), [single(Keycode::D)]);
+        assert_eq!(keys(section.removed()), [single(Keycode::C)]);
+
+        // twice as many inputs, yet the same share
+        let a = change(section, Keycode::A);
+        assert_eq!(a.delta(), 4);
+        assert_eq!(a.share_change(), 0.0);
+        assert_eq!(a.relative(), Some(0.0));
+
+        let b = change(section, Keycode::B);
+        assert_eq!((b.share_before, b.share_after), (0.25, 0.375));
+        assert_eq!(b.share_change(), 12.5);
+        assert_eq!(b.relative(), Some(0.5));
+
+        let d = change(section, Keycode::D);
+        assert!(d.is_added());
+        assert_eq!(d.relative(), None);
+
+        // the largest change either way first, ties by key
+        assert_eq!(
+            keys(section.top_movers(10)),
+            [single(Keycode::C), single(Keycode::B), single(Keycode::D)]
+        );
+        assert_eq!(keys(section.top_movers(1)), [single(Keycode::C)]);
+    }
+
+    #[test]
+    fn sections_of_either_file() {
+        let before = KeyCounts::from_yaml("A: 1\n").unwrap();
+        let mut after = KeyCounts::new();
+        let pairs = Mode::new(2, true);
+        after
+            .counts_mut(pairs)
+            .insert(CountItem::Pair(vec![Keycode::A], vec![Keycode::B]), 3);
+
+        let sections = diff(&before, &after);
+        let modes: Vec<Mode> = sections.iter().map(|section| section.mode).collect();
+        assert_eq!(modes, [Mode::new(1, true), pairs]);
+
+        let chords = &sections[0];
+        assert_eq!((chords.total_before, chords.total_after), (1, 0));
+        assert_eq!(chords.changes[0].share_after, 0.0);
+        assert!(chords.changes[0].is_removed());
+        assert_eq!(sections[1].added().count(), 1);
+    }
+
+    #[test]
+    fn json() {
+        let before = KeyCounts::from_yaml("A: 1\nB: 1\n").unwrap();
+        let after = KeyCounts::from_yaml("A: 3\nC: 1\n").unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&to_json(&diff(&before, &after), 1)).unwrap();
+        let section = &json[0];
+        assert_eq!(section["section"], "chords");
+        assert_eq!(section["added"], serde_json::json!(["C"]));
+        assert_eq!(section["removed"], serde_json::json!(["B"]));
+        assert_eq!(section["top_movers"].as_array().unwrap().len(), 1);
+        assert_eq!(section["changes"].as_array().unwrap().len(), 3);
+    }
+}
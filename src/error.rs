@@ -0,0 +1,95 @@
+//! Errors of every fallible operation in the crate.
+
+use core::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Setting of [`Config`][crate::Config] that can differ between a file and the requested options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    Collect,
+    Ngram,
+    NoChords,
+    Dwell,
+    Flight,
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Setting::Collect => "collect",
+            Setting::Ngram => "ngram",
+            Setting::NoChords => "no_chords",
+            Setting::Dwell => "dwell",
+            Setting::Flight => "flight",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing failed, `path` is `None` for the terminal.
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// The file at `path` does not hold valid data.
+    Parse { path: PathBuf, message: String },
+    /// A file was created with other settings than the requested ones.
+    ConfigMismatch {
+        setting: Setting,
+        /// Requested value.
+        options: String,
+        /// Value in the file.
+        file: String,
+    },
+    /// The user declined to go on.
+    Aborted,
+}
+
+impl Error {
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        Error::Io {
+            path: Some(path.to_path_buf()),
+            source,
+        }
+    }
+
+    pub fn parse(path: &Path, message: impl fmt::Display) -> Self {
+        Error::Parse {
+            path: path.to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{:?}: {}", path, source),
+            Error::Io { path: None, source } => write!(f, "terminal: {}", source),
+            Error::Parse { path, message } => write!(f, "{:?} is not valid: {}", path, message),
+            Error::ConfigMismatch {
+                setting,
+                options,
+                file,
+            } => write!(f, "{} is {} when in file {}", setting, options, file),
+            Error::Aborted => write!(f, "aborted"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
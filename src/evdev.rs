@@ -0,0 +1,252 @@
+//! Linux evdev backend.
+//!
+//! Reads `struct input_event` records straight from `/dev/input/event*`,
+//! so every press and release is seen with its kernel timestamp no matter
+//! how fast it was, and no X session is needed. Devices are asked for
+//! timestamps of the monotonic clock, which a change of the wall clock does not move.
+//!
+//! The same reader accepts a file with recorded events
+//! (e.g. `cat /dev/input/event3 > keys.bin`), which ends the capture
+//! once all of it has been consumed.
+
+use crate::keycode::keycode_from_kernel;
+use crate::source::{KeyEvent, KeySource};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+const EV_KEY: u16 = 0x01;
+const EV_REP: u16 = 0x14;
+
+const KEY_RELEASE: i32 = 0;
+const KEY_PRESS: i32 = 1;
+
+/// `struct timeval` is two `long`s, which have pointer width on Linux.
+const TIME_FIELD_SIZE: usize = std::mem::size_of::<usize>();
+const INPUT_EVENT_SIZE: usize = 2 * TIME_FIELD_SIZE + 8;
+
+/// How long [`EvdevSource::poll`] waits for events before returning none,
+/// so the caller gets a chance to stop.
+const POLL_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// `EVIOCSCLOCKID`, that is `_IOW('E', 0xa0, int)`: set the clock of event timestamps.
+const EVIOCSCLOCKID: u32 = 0x4004_45a0;
+
+const INPUT_DEVICES_PATH: &str = "/proc/bus/input/devices";
+const DEV_INPUT_PATH: &str = "/dev/input";
+
+/// One decoded `struct input_event`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    pub time: Duration,
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+impl InputEvent {
+    pub fn from_bytes(bytes: &[u8; INPUT_EVENT_SIZE]) -> Self {
+        let (sec, rest) = bytes.split_at(TIME_FIELD_SIZE);
+        let (usec, rest) = rest.split_at(TIME_FIELD_SIZE);
+
+        let field = |bytes: &[u8]| -> u64 {
+            let mut buffer = [0; 8];
+            buffer[..bytes.len()].copy_from_slice(bytes);
+            u64::from_ne_bytes(buffer)
+        };
+
+        Self {
+            time: Duration::from_secs(field(sec)) + Duration::from_micros(field(usec)),
+            kind: u16::from_ne_bytes([rest[0], rest[1]]),
+            code: u16::from_ne_bytes([rest[2], rest[3]]),
+            value: i32::from_ne_bytes([rest[4], rest[5], rest[6], rest[7]]),
+        }
+    }
+}
+
+/// Read events from `reader` until it ends or fails.
+fn read_events(mut reader: impl Read, path: &Path, sender: Sender<InputEvent>) {
+    let mut buffer = [0; INPUT_EVENT_SIZE];
+    loop {
+        match reader.read_exact(&mut buffer) {
+            Ok(()) => {
+                if sender.send(InputEvent::from_bytes(&buffer)).is_err() {
+                    return;
+                }
+            }
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                log::debug!("{:?} ended", path);
+                return;
+            }
+            Err(error) => {
+                log::error!("cannot read events from {:?}: {}", path, error);
+                return;
+            }
+        }
+    }
+}
+
+/// Have `file` timestamp events by the monotonic clock instead of the wall clock.
+/// A recorded event file is not a device and keeps the timestamps it was recorded with.
+fn use_monotonic_clock(file: &File, path: &Path) {
+    let clock: libc::c_int = libc::CLOCK_MONOTONIC;
+    // SAFETY: the descriptor stays open for the call and the request reads one int
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), EVIOCSCLOCKID as _, &clock) };
+    if result != 0 {
+        log::debug!("{:?} keeps its clock: {}", path, io::Error::last_os_error());
+    }
+}
+
+/// Event devices that look like keyboards:
+/// handled by `kbd` and able to autorepeat, which leaves out power buttons and such.
+pub fn find_keyboards() -> io::Result<Vec<PathBuf>> {
+    let devices = fs::read_to_string(INPUT_DEVICES_PATH)?;
+    let mut keyboards = Vec::new();
+
+    for device in devices.split("\n\n") {
+        let mut handlers = Vec::new();
+        let mut events = 0u64;
+
+        for line in device.lines() {
+            if let Some(list) = line.strip_prefix("H: Handlers=") {
+                handlers = list.split_whitespace().collect();
+            } else if let Some(mask) = line.strip_prefix("B: EV=") {
+                events = u64::from_str_radix(mask.trim(), 16).unwrap_or(0);
+            }
+        }
+
+        let is_keyboard =
+            handlers.contains(&"kbd") && events & (1 << EV_KEY) != 0 && events & (1 << EV_REP) != 0;
+        if !is_keyboard {
+            continue;
+        }
+
+        if let Some(event) = handlers.iter().find(|handler| handler.starts_with("event")) {
+            keyboards.push(Path::new(DEV_INPUT_PATH).join(event));
+        }
+    }
+
+    Ok(keyboards)
+}
+
+/// Produces key press and release events, with their monotonic kernel timestamps,
+/// read from one or more event devices or recorded event files.
+pub struct EvdevSource {
+    events: Receiver<InputEvent>,
+}
+
+impl EvdevSource {
+    /// Open every path in `paths`, or every keyboard found when it is empty.
+    pub fn open(paths: &[PathBuf]) -> io::Result<Self> {
+        let paths = if paths.is_empty() {
+            find_keyboards()?
+        } else {
+            paths.to_vec()
+        };
+
+        if paths.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no keyboard event device found",
+            ));
+        }
+
+        let (sender, events) = mpsc::channel();
+        for path in paths {
+            let file = File::open(&path)
+                .map_err(|error| io::Error::new(error.kind(), format!("{:?}: {}", path, error)))?;
+            use_monotonic_clock(&file, &path);
+            let sender = sender.clone();
+            thread::spawn(move || read_events(file, &path, sender));
+        }
+
+        Ok(Self { events })
+    }
+}
+
+fn key_event(event: InputEvent) -> Option<KeyEvent> {
+    if event.kind != EV_KEY {
+        return None;
+    }
+    let key = keycode_from_kernel(event.code)?;
+
+    match event.value {
+        KEY_PRESS => Some(KeyEvent::press(key, event.time)),
+        KEY_RELEASE => Some(KeyEvent::release(key, event.time)),
+        // autorepeat
+        _ => None,
+    }
+}
+
+impl KeySource for EvdevSource {
+    fn poll(&mut self) -> Option<Vec<KeyEvent>> {
+        // every reader has finished once all senders are dropped
+        let first = match self.events.recv_timeout(POLL_TIMEOUT) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => return Some(Vec::new()),
+            Err(RecvTimeoutError::Disconnected) => return None,
+        };
+
+        Some(
+            std::iter::once(first)
+                .chain(self.events.try_iter())
+                .filter_map(key_event)
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TempFile;
+    use device_query::Keycode;
+
+    const EV_SYN: u16 = 0x00;
+    const KEY_AUTOREPEAT: i32 = 2;
+    const KEY_A: u16 = 30;
+
+    fn record(micros: u64, kind: u16, code: u16, value: i32) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(INPUT_EVENT_SIZE);
+        bytes.extend((micros as usize / 1_000_000).to_ne_bytes());
+        bytes.extend((micros as usize % 1_000_000).to_ne_bytes());
+        bytes.extend(kind.to_ne_bytes());
+        bytes.extend(code.to_ne_bytes());
+        bytes.extend(value.to_ne_bytes());
+        bytes
+    }
+
+    #[test]
+    fn recorded_event_file() {
+        let path = TempFile::new("recorded-events.bin");
+        let records = [
+            record(1_000_000, EV_KEY, KEY_A, KEY_PRESS),
+            record(1_000_000, EV_SYN, 0, 0),
+            record(1_250_000, EV_KEY, KEY_A, KEY_AUTOREPEAT),
+            record(1_250_000, EV_SYN, 0, 0),
+            record(1_300_500, EV_KEY, KEY_A, KEY_RELEASE),
+            record(1_300_500, EV_SYN, 0, 0),
+        ];
+        fs::write(&path, records.concat()).unwrap();
+
+        let mut source = EvdevSource::open(&[path.to_path_buf()]).unwrap();
+        let mut events = Vec::new();
+        while let Some(batch) = source.poll() {
+            events.extend(batch);
+        }
+
+        assert_eq!(
+            events,
+            [
+                KeyEvent::press(Keycode::A, Duration::from_secs(1)),
+                KeyEvent::release(Keycode::A, Duration::from_micros(1_300_500)),
+            ]
+        );
+        // the stream stays ended
+        assert_eq!(source.poll(), None);
+    }
+}
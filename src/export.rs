@@ -0,0 +1,153 @@
+//! Counts as CSV or TSV tables, for spreadsheets and data frames.
+//!
+//! Every [`CountItem`] makes one row: a column per key of each input, then the count.
+//! Singles have columns `key1`, `key2`, … and longer items
+//! `input1_key1`, `input1_key2`, …, `input2_key1`, …, each input getting as many
+//! columns as its largest chord in the table. Unused cells are empty.
+//! Rows go from the most to the least frequent.
+
+use crate::counts::{keycode_to_string, CountItem, HashMap};
+use device_query::Keycode;
+use std::cmp::Reverse;
+use std::io::{self, Write};
+
+pub const CSV_DELIMITER: char = ',';
+pub const TSV_DELIMITER: char = '\t';
+
+/// Write `counts` of items made of `ngram` inputs as a table.
+pub fn write_table(
+    writer: &mut impl Write,
+    counts: &HashMap<CountItem, u32>,
+    ngram: usize,
+    delimiter: char,
+) -> io::Result<()> {
+    let mut rows: Vec<(Vec<&[Keycode]>, u32)> = counts
+        .iter()
+        .map(|(count_item, count)| (count_item.inputs(), *count))
+        .collect();
+    rows.sort_by_cached_key(|(inputs, count)| {
+        let names: Vec<Vec<String>> = inputs
+            .iter()
+            .map(|input| input.iter().map(keycode_to_string).collect())
+            .collect();
+        (Reverse(*count), names)
+    });
+
+    // the widest chord at each position
+    let inputs = rows
+        .iter()
+        .map(|(inputs, _)| inputs.len())
+        .max()
+        .unwrap_or(ngram)
+        .max(ngram);
+    let widths: Vec<usize> = (0..inputs)
+        .map(|position| {
+            rows.iter()
+                .filter_map(|(inputs, _)| inputs.get(position).map(|input| input.len()))
+                .max()
+                .unwrap_or(1)
+                .max(1)
+        })
+        .collect();
+
+    let mut header = Vec::new();
+    for (position, width) in widths.iter().enumerate() {
+        for key in 1..=*width {
+            header.push(if inputs == 1 {
+                format!("key{}", key)
+            } else {
+                format!("input{}_key{}", position + 1, key)
+            });
+        }
+    }
+    header.push("count".into());
+    write_row(writer, &header, delimiter)?;
+
+    for (item_inputs, count) in &rows {
+        let mut row = Vec::new();
+        for (position, width) in widths.iter().enumerate() {
+            let input = item_inputs.get(position).copied().unwrap_or_default();
+            for index in 0..*width {
+                row.push(input.get(index).map(keycode_to_string).unwrap_or_default());
+            }
+        }
+        row.push(count.to_string());
+        write_row(writer, &row, delimiter)?;
+    }
+
+    Ok(())
+}
+
+fn write_row(writer: &mut impl Write, cells: &[String], delimiter: char) -> io::Result<()> {
+    let cells: Vec<String> = cells
+        .iter()
+        .map(|cell| {
+            if cell.contains([delimiter, '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.clone()
+            }
+        })
+        .collect();
+
+    writeln!(writer, "{}", cells.join(&delimiter.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counts::parse_count_item;
+
+    fn table(items: &[(&str, u32)], ngram: usize, delimiter: char) -> String {
+        let counts: HashMap<CountItem, u32> = items
+            .iter()
+            .map(|(item, count)| (parse_count_item(item).unwrap(), *count))
+            .collect();
+        let mut table = Vec::new();
+        write_table(&mut table, &counts, ngram, delimiter).unwrap();
+        String::from_utf8(table).unwrap()
+    }
+
+    fn row(cells: &[&str], delimiter: char) -> String {
+        let cells: Vec<String> = cells.iter().map(|cell| cell.to_string()).collect();
+        let mut row = Vec::new();
+        write_row(&mut row, &cells, delimiter).unwrap();
+        String::from_utf8(row).unwrap()
+    }
+
+    #[test]
+    fn singles() {
+        assert_eq!(
+            table(&[("B", 2), ("A+LShift", 3), ("Comma", 2)], 1, CSV_DELIMITER),
+            "key1,key2,count\nA,LShift,3\nB,,2\nComma,,2\n"
+        );
+    }
+
+    #[test]
+    fn pairs() {
+        assert_eq!(
+            table(&[("A, B+LShift", 1), ("C, D", 2)], 2, TSV_DELIMITER),
+            "input1_key1\tinput2_key1\tinput2_key2\tcount\nC\tD\t\t2\nA\tB\tLShift\t1\n"
+        );
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(
+            table(&[], 3, CSV_DELIMITER),
+            "input1_key1,input2_key1,input3_key1,count\n"
+        );
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(
+            row(&["a,b", "c\td", "say \"hi\"", "line\nbreak"], CSV_DELIMITER),
+            "\"a,b\",c\td,\"say \"\"hi\"\"\",\"line\nbreak\"\n"
+        );
+        assert_eq!(
+            row(&["a,b", "c\td", "plain"], TSV_DELIMITER),
+            "a,b\t\"c\td\"\tplain\n"
+        );
+    }
+}
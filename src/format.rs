@@ -0,0 +1,76 @@
+//! Formats a statistics file can be written in.
+
+use core::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const YAML_FORMAT: &str = "yaml";
+pub const JSON_FORMAT: &str = "json";
+pub const BINARY_FORMAT: &str = "binary";
+#[cfg(feature = "sqlite")]
+pub const SQLITE_FORMAT: &str = "sqlite";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Yaml,
+    Json,
+    /// See [`crate::binary`].
+    Binary,
+    /// See [`crate::sqlite`].
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+impl Format {
+    /// Format told by the extension of `path`, if it is a known one.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "yaml" | "yml" => Some(Format::Yaml),
+            "json" => Some(Format::Json),
+            "hkcs" => Some(Format::Binary),
+            #[cfg(feature = "sqlite")]
+            "sqlite" | "sqlite3" | "db" => Some(Format::Sqlite),
+            _ => None,
+        }
+    }
+
+    /// Extension of files in this format, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Yaml => "yaml",
+            Format::Json => "json",
+            Format::Binary => "hkcs",
+            #[cfg(feature = "sqlite")]
+            Format::Sqlite => "sqlite",
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Yaml => write!(f, "{}", YAML_FORMAT),
+            Format::Json => write!(f, "{}", JSON_FORMAT),
+            Format::Binary => write!(f, "{}", BINARY_FORMAT),
+            #[cfg(feature = "sqlite")]
+            Format::Sqlite => write!(f, "{}", SQLITE_FORMAT),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            YAML_FORMAT => Ok(Format::Yaml),
+            JSON_FORMAT => Ok(Format::Json),
+            BINARY_FORMAT => Ok(Format::Binary),
+            #[cfg(feature = "sqlite")]
+            SQLITE_FORMAT => Ok(Format::Sqlite),
+            _ => Err(format!("{} is not a known format", s)),
+        }
+    }
+}
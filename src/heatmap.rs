@@ -0,0 +1,495 @@
+//! How often each key is pressed, placed on a keyboard [`Layout`].
+//!
+//! A layout is described as text, one line per row of keys. Every word is a key
+//! as written in statistics files (`A`, `Key1`, `LShift`…), optionally followed
+//! by `:<width>` in key widths, 1 if not given; `_` is a gap:
+//!
+//! ```text
+//! Tab:1.5 Q W E R T Y U I O P LeftBracket RightBracket BackSlash:1.5
+//! LShift:2.25 Z X C V B N M Comma Dot Slash RShift:2.75 _:1.25 Up
+//! ```
+//!
+//! A key may take several places (ISO `Enter` spans two rows). Besides the
+//! built-in layouts, a description can be read from a file with [`load_layout`].
+//!
+//! [`render`] draws a layout for the terminal, [`KEY_COLUMNS`] wide per key width.
+
+use crate::counts::{keycode_to_string, parse_keycode_from_string, CountItem, HashMap};
+use crate::error::{Error, Result};
+use colored::{Color, Colorize};
+use core::fmt;
+use device_query::Keycode;
+use std::cmp::Reverse;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const ANSI_LAYOUT: &str = "ansi";
+pub const ISO_LAYOUT: &str = "iso";
+pub const ORTHO_LAYOUT: &str = "ortho";
+pub const SPLIT_LAYOUT: &str = "split";
+
+/// Terminal columns of a key one key width wide, the last one parts it from the next key.
+pub const KEY_COLUMNS: f64 = 5.0;
+/// Shades of [`render`], the least pressed keys first.
+pub const SHADES: [Color; 5] = [
+    Color::Blue,
+    Color::Cyan,
+    Color::Green,
+    Color::Yellow,
+    Color::Red,
+];
+
+const ANSI: &str = "
+Escape _ F1 F2 F3 F4 _:0.5 F5 F6 F7 F8 _:0.5 F9 F10 F11 F12
+Grave Key1 Key2 Key3 Key4 Key5 Key6 Key7 Key8 Key9 Key0 Minus Equal Backspace:2 _:0.25 Insert Home PageUp
+Tab:1.5 Q W E R T Y U I O P LeftBracket RightBracket BackSlash:1.5 _:0.25 Delete End PageDown
+CapsLock:1.75 A S D F G H J K L Semicolon Apostrophe Enter:2.25
+LShift:2.25 Z X C V B N M Comma Dot Slash RShift:2.75 _:1.25 Up
+LControl:1.25 LMeta:1.25 LAlt:1.25 Space:6.25 RAlt:1.25 RMeta:1.25 _:1.25 RControl:1.25 _:0.25 Left Down Right
+";
+
+const ISO: &str = "
+Escape _ F1 F2 F3 F4 _:0.5 F5 F6 F7 F8 _:0.5 F9 F10 F11 F12
+Grave Key1 Key2 Key3 Key4 Key5 Key6 Key7 Key8 Key9 Key0 Minus Equal Backspace:2 _:0.25 Insert Home PageUp
+Tab:1.5 Q W E R T Y U I O P LeftBracket RightBracket Enter:1.5 _:0.25 Delete End PageDown
+CapsLock:1.75 A S D F G H J K L Semicolon Apostrophe BackSlash Enter:1.25
+LShift:1.25 _ Z X C V B N M Comma Dot Slash RShift:2.75 _:1.25 Up
+LControl:1.25 LMeta:1.25 LAlt:1.25 Space:6.25 RAlt:1.25 RMeta:1.25 _:1.25 RControl:1.25 _:0.25 Left Down Right
+";
+
+const ORTHO: &str = "
+Escape Key1 Key2 Key3 Key4 Key5 Key6 Key7 Key8 Key9 Key0 Backspace
+Tab Q W E R T Y U I O P BackSlash
+CapsLock A S D F G H J K L Semicolon Apostrophe
+LShift Z X C V B N M Comma Dot Slash Enter
+LControl LMeta LAlt Grave Minus Space:2 Equal Left Down Up Right
+";
+
+const SPLIT: &str = "
+Equal Key1 Key2 Key3 Key4 Key5 Escape _:2 Grave Key6 Key7 Key8 Key9 Key0 Minus
+Tab Q W E R T LeftBracket _:2 RightBracket Y U I O P BackSlash
+CapsLock A S D F G _:4 H J K L Semicolon Apostrophe
+LShift Z X C V B _:4 N M Comma Dot Slash RShift
+LControl LMeta LAlt Left Right _:6 Up Down RAlt RMeta RControl
+_:5 Backspace Delete _:2 Enter Space
+";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// Row-staggered, US style.
+    #[default]
+    Ansi,
+    /// Row-staggered with a two row `Enter` and a short left shift.
+    Iso,
+    /// Keys in a grid of 12 columns.
+    Ortho,
+    /// Two halves of 7 columns with keys for the thumbs.
+    Split,
+}
+
+impl Layout {
+    /// Text this layout is described in, see the [module](self) documentation.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Layout::Ansi => ANSI,
+            Layout::Iso => ISO,
+            Layout::Ortho => ORTHO,
+            Layout::Split => SPLIT,
+        }
+    }
+
+    /// Rows of keys, the top one first.
+    pub fn rows(&self) -> Vec<Vec<Slot>> {
+        parse_layout(self.description()).expect("built-in layouts are valid")
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Layout::Ansi => write!(f, "{}", ANSI_LAYOUT),
+            Layout::Iso => write!(f, "{}", ISO_LAYOUT),
+            Layout::Ortho => write!(f, "{}", ORTHO_LAYOUT),
+            Layout::Split => write!(f, "{}", SPLIT_LAYOUT),
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            ANSI_LAYOUT => Ok(Layout::Ansi),
+            ISO_LAYOUT => Ok(Layout::Iso),
+            ORTHO_LAYOUT => Ok(Layout::Ortho),
+            SPLIT_LAYOUT => Ok(Layout::Split),
+            _ => Err(format!("{} is not a known layout", s)),
+        }
+    }
+}
+
+/// One place of a row: a key, or a gap when `key` is `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slot {
+    pub key: Option<Keycode>,
+    /// In key widths.
+    pub width: f64,
+}
+
+/// Parse a layout description, see the [module](self) documentation.
+pub fn parse_layout(description: &str) -> std::result::Result<Vec<Vec<Slot>>, String> {
+    description
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            line.split_whitespace()
+                .map(parse_slot)
+                .collect::<std::result::Result<_, _>>()
+                .map_err(|message| format!("line {}: {}", index + 1, message))
+        })
+        .collect()
+}
+
+/// Read and parse the layout description at `path`.
+pub fn load_layout(path: &Path) -> Result<Vec<Vec<Slot>>> {
+    let description = fs::read_to_string(path).map_err(|error| Error::io(path, error))?;
+    let rows = parse_layout(&description).map_err(|message| Error::parse(path, message))?;
+    if rows.iter().flatten().all(|slot| slot.key.is_none()) {
+        return Err(Error::parse(path, "layout has no keys"));
+    }
+
+    Ok(rows)
+}
+
+fn parse_slot(word: &str) -> std::result::Result<Slot, String> {
+    let (name, width) = match word.split_once(':') {
+        Some((name, width)) => match width.parse::<f64>() {
+            Ok(width) if width.is_finite() && width > 0.0 => (name, width),
+            _ => return Err(format!("{:?} is not a key width", width)),
+        },
+        None => (word, 1.0),
+    };
+    let key = match name {
+        "_" => None,
+        name => Some(parse_keycode_from_string(name)?),
+    };
+
+    Ok(Slot { key, width })
+}
+
+/// Short name of `key` to print on a diagram, fitting in `columns` if it can.
+pub fn label(key: &Keycode, columns: usize) -> String {
+    let label = match key {
+        Keycode::Key0 => "0",
+        Keycode::Key1 => "1",
+        Keycode::Key2 => "2",
+        Keycode::Key3 => "3",
+        Keycode::Key4 => "4",
+        Keycode::Key5 => "5",
+        Keycode::Key6 => "6",
+        Keycode::Key7 => "7",
+        Keycode::Key8 => "8",
+        Keycode::Key9 => "9",
+        Keycode::Grave => "`",
+        Keycode::Minus => "-",
+        Keycode::Equal => "=",
+        Keycode::LeftBracket => "[",
+        Keycode::RightBracket => "]",
+        Keycode::BackSlash => "\\",
+        Keycode::Semicolon => ";",
+        Keycode::Apostrophe => "'",
+        Keycode::Comma => ",",
+        Keycode::Dot => ".",
+        Keycode::Slash => "/",
+        Keycode::Escape => "Esc",
+        Keycode::Backspace => "Bksp",
+        Keycode::CapsLock => "Caps",
+        Keycode::LShift | Keycode::RShift => "Shift",
+        Keycode::LControl | Keycode::RControl => "Ctrl",
+        Keycode::LAlt | Keycode::RAlt => "Alt",
+        Keycode::LMeta | Keycode::RMeta => "Meta",
+        Keycode::Insert => "Ins",
+        Keycode::Delete => "Del",
+        Keycode::PageUp => "PgUp",
+        Keycode::PageDown => "PgDn",
+        Keycode::Left => "Lft",
+        Keycode::Right => "Rgt",
+        Keycode::Down => "Dn",
+        key => return fit(keycode_to_string(key), key, columns),
+    };
+
+    fit(label.into(), key, columns)
+}
+
+fn fit(label: String, key: &Keycode, columns: usize) -> String {
+    if label.len() <= columns {
+        return label;
+    }
+
+    // narrow keys of ortholinear and split layouts
+    match key {
+        Keycode::LShift | Keycode::RShift => "Sft",
+        Keycode::Enter => "Ent",
+        Keycode::Space => "Spc",
+        _ => return label,
+    }
+    .into()
+}
+
+/// How many times each key was pressed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Heatmap {
+    pub presses: HashMap<Keycode, u64>,
+    pub total: u64,
+}
+
+impl Heatmap {
+    /// Presses of every key of every input of the items of `counts`.
+    pub fn new(counts: &HashMap<CountItem, u32>) -> Self {
+        let mut heatmap = Self::default();
+        for (item, count) in counts {
+            for key in item.inputs().into_iter().flatten() {
+                *heatmap.presses.entry(*key).or_insert(0) += *count as u64;
+                heatmap.total += *count as u64;
+            }
+        }
+
+        heatmap
+    }
+
+    pub fn count(&self, key: &Keycode) -> u64 {
+        self.presses.get(key).copied().unwrap_or(0)
+    }
+
+    /// Share of every press, from 0 to 1.
+    pub fn share(&self, key: &Keycode) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.count(key) as f64 / self.total as f64
+        }
+    }
+
+    /// Presses of the most pressed key.
+    pub fn max(&self) -> u64 {
+        self.presses.values().copied().max().unwrap_or(0)
+    }
+
+    /// Shade of `key` from 0 to `levels - 1`, relative to the most pressed key;
+    /// `None` for keys never pressed, or without any level.
+    pub fn level(&self, key: &Keycode, levels: usize) -> Option<usize> {
+        let count = self.count(key);
+        if count == 0 || levels == 0 {
+            return None;
+        }
+
+        let level = (count as f64 / self.max() as f64 * levels as f64).ceil() as usize;
+        Some(level.clamp(1, levels) - 1)
+    }
+
+    /// Pressed keys without a place in `rows`, the most pressed first.
+    pub fn missing(&self, rows: &[Vec<Slot>]) -> Vec<(Keycode, u64)> {
+        let mut missing: Vec<(Keycode, u64)> = self
+            .presses
+            .iter()
+            .filter(|(key, _)| !rows.iter().flatten().any(|slot| slot.key == Some(**key)))
+            .map(|(key, count)| (*key, *count))
+            .collect();
+        missing.sort_by_cached_key(|(key, count)| (Reverse(*count), keycode_to_string(key)));
+
+        missing
+    }
+}
+
+/// Terminal columns [`render`] takes for `rows`.
+pub fn columns(rows: &[Vec<Slot>]) -> usize {
+    rows.iter()
+        .map(|row| (row.iter().map(|slot| slot.width).sum::<f64>() * KEY_COLUMNS).round() as usize)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Lines drawing `rows`, two per row: key labels and their share of presses.
+///
+/// Pressed keys are shaded by one of `colors`, the least pressed first,
+/// never pressed ones are dimmed; nothing is colored when `colors` is empty.
+pub fn render(heatmap: &Heatmap, rows: &[Vec<Slot>], colors: &[Color]) -> Vec<String> {
+    // a key placed several times is named once
+    let mut labeled: Vec<Keycode> = Vec::new();
+    let mut lines = Vec::new();
+
+    for row in rows {
+        let mut label_line = String::new();
+        let mut share_line = String::new();
+        let mut position = 0.0;
+        for slot in row {
+            let start = (position * KEY_COLUMNS).round() as usize;
+            position += slot.width;
+            let end = (position * KEY_COLUMNS).round() as usize;
+            let columns = end.saturating_sub(start).saturating_sub(1);
+
+            // gaps, and keys too narrow to draw, are left blank
+            let Some(key) = slot.key.filter(|_| columns > 0) else {
+                label_line.push_str(&" ".repeat(end.saturating_sub(start)));
+                share_line.push_str(&" ".repeat(end.saturating_sub(start)));
+                continue;
+            };
+
+            let (label, share) = if labeled.contains(&key) {
+                (String::new(), String::new())
+            } else {
+                labeled.push(key);
+                (label(&key, columns), share_text(heatmap, &key))
+            };
+            let cell = |text: &str| {
+                let text: String = text.chars().take(columns).collect();
+                let text = format!("{:^columns$}", text);
+                if colors.is_empty() {
+                    return text;
+                }
+                match heatmap.level(&key, colors.len()) {
+                    Some(level) => text.black().on_color(colors[level]).to_string(),
+                    None => text.dimmed().to_string(),
+                }
+            };
+            label_line.push_str(&format!("{} ", cell(&label)));
+            share_line.push_str(&format!("{} ", cell(&share)));
+        }
+        lines.push(label_line.trim_end().to_string());
+        lines.push(share_line.trim_end().to_string());
+    }
+
+    lines
+}
+
+/// Share of presses of `key` in whole percents, empty for keys never pressed.
+fn share_text(heatmap: &Heatmap, key: &Keycode) -> String {
+    if heatmap.count(key) == 0 {
+        String::new()
+    } else if heatmap.share(key) < 0.01 {
+        "<1%".into()
+    } else {
+        format!("{:.0}%", heatmap.share(key) * 100.0)
+    }
+}
+
+/// One cell per color of [`render`] with the largest share it stands for.
+pub fn legend(heatmap: &Heatmap, colors: &[Color]) -> Vec<String> {
+    let max_share = heatmap.max() as f64 / heatmap.total.max(1) as f64;
+
+    colors
+        .iter()
+        .enumerate()
+        .map(|(level, color)| {
+            let up_to = max_share * (level + 1) as f64 / colors.len() as f64;
+            format!(" <={:.1}% ", up_to * 100.0)
+                .black()
+                .on_color(*color)
+                .to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heatmap(counts: &[(Keycode, u32)]) -> Heatmap {
+        let counts: HashMap<CountItem, u32> = counts
+            .iter()
+            .map(|(key, count)| (CountItem::Single(vec![*key]), *count))
+            .collect();
+        Heatmap::new(&counts)
+    }
+
+    #[test]
+    fn built_in_layouts() {
+        for layout in [Layout::Ansi, Layout::Iso, Layout::Ortho, Layout::Split] {
+            assert!(parse_layout(layout.description()).is_ok(), "{}", layout);
+            assert_eq!(layout.to_string().parse::<Layout>(), Ok(layout));
+        }
+    }
+
+    #[test]
+    fn bad_layouts() {
+        assert!(parse_layout("A Nope").is_err());
+        assert!(parse_layout("A:0").is_err());
+        assert!(parse_layout("A:-1").is_err());
+        assert!(parse_layout("A:wide").is_err());
+        assert_eq!(
+            parse_layout("A B\n\nC Nope").unwrap_err(),
+            parse_layout("Nope")
+                .unwrap_err()
+                .replace("line 1", "line 3")
+        );
+    }
+
+    #[test]
+    fn layout_files() {
+        let path = crate::temp::TempFile::new("layout-file.txt");
+
+        fs::write(&path, "Escape _ F1\nA:2 B\n").unwrap();
+        let rows = load_layout(&path).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            rows[1][0],
+            Slot {
+                key: Some(Keycode::A),
+                width: 2.0
+            }
+        );
+        assert_eq!(rows[0][1].key, None);
+
+        fs::write(&path, "_ _\n").unwrap();
+        assert!(matches!(load_layout(&path), Err(Error::Parse { .. })));
+        fs::write(&path, "A Nope\n").unwrap();
+        assert!(matches!(load_layout(&path), Err(Error::Parse { .. })));
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(load_layout(&path), Err(Error::Io { .. })));
+    }
+
+    #[test]
+    fn levels() {
+        let heatmap = heatmap(&[(Keycode::A, 100), (Keycode::B, 50), (Keycode::C, 1)]);
+
+        assert_eq!(heatmap.level(&Keycode::A, 5), Some(4));
+        assert_eq!(heatmap.level(&Keycode::B, 5), Some(2));
+        assert_eq!(heatmap.level(&Keycode::C, 5), Some(0));
+        assert_eq!(heatmap.level(&Keycode::D, 5), None);
+        assert_eq!(heatmap.level(&Keycode::A, 0), None);
+    }
+
+    #[test]
+    fn render_plain() {
+        let heatmap = heatmap(&[(Keycode::A, 3), (Keycode::LShift, 1), (Keycode::Numpad1, 1)]);
+        let rows = parse_layout("A B _ Enter:2\nLShift:2 Enter").unwrap();
+
+        assert_eq!(
+            render(&heatmap, &rows, &[]),
+            [" A    B          Enter", "60%", "  Shift", "   20%",]
+        );
+        assert_eq!(columns(&rows), 25);
+        assert_eq!(heatmap.missing(&rows), [(Keycode::Numpad1, 1)]);
+    }
+
+    #[test]
+    fn render_too_narrow_keys() {
+        let heatmap = heatmap(&[(Keycode::A, 1)]);
+        let rows = parse_layout("A:0.1 B:0.1 Space:0.2 C").unwrap();
+
+        assert_eq!(render(&heatmap, &rows, &SHADES).len(), 2);
+        assert_eq!(render(&heatmap, &rows, &[]), ["   C", ""]);
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(label(&Keycode::Key1, 4), "1");
+        assert_eq!(label(&Keycode::LShift, 9), "Shift");
+        assert_eq!(label(&Keycode::LShift, 4), "Sft");
+        assert_eq!(label(&Keycode::Space, 4), "Spc");
+        assert_eq!(label(&Keycode::F12, 4), "F12");
+    }
+}
@@ -0,0 +1,333 @@
+//! Write-ahead journal of count increments.
+//!
+//! Every increment is appended to `<path>.journal` as one line as soon as it
+//! is counted, while the statistics file itself is rewritten only from time to time.
+//! The journal starts with a header carrying its generation; a statistics file
+//! saved with [`KeyCounts::journal`] equal to that generation already includes
+//! every entry, so a journal left behind is replayed only when it is newer.
+//!
+//! Entries are tab separated fields, times are in microseconds:
+//!
+//! ```text
+//! hectic-key-capture journal 3
+//! count <TAB> chords <TAB> A+LShift
+//! dwell <TAB> A <TAB> 85000
+//! flight <TAB> chord-pairs <TAB> A, B <TAB> 120000
+//! ```
+
+use crate::counts::{
+    count_item_to_string, keycode_to_string, parse_count_item, parse_keycode_from_string,
+    CountItem, KeyCounts, Mode,
+};
+use crate::error::{Error, Result};
+use device_query::Keycode;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const HEADER: &str = "hectic-key-capture journal";
+
+/// One increment of [`KeyCounts`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    Count(Mode, CountItem),
+    Dwell(Keycode, Duration),
+    Flight(Mode, CountItem, Duration),
+}
+
+impl Entry {
+    fn to_line(&self) -> String {
+        match self {
+            Entry::Count(mode, count_item) => {
+                format!("count\t{}\t{}", mode, count_item_to_string(count_item))
+            }
+            Entry::Dwell(key, hold) => {
+                format!("dwell\t{}\t{}", keycode_to_string(key), hold.as_micros())
+            }
+            Entry::Flight(mode, count_item, flight) => format!(
+                "flight\t{}\t{}\t{}",
+                mode,
+                count_item_to_string(count_item),
+                flight.as_micros()
+            ),
+        }
+    }
+
+    fn from_line(line: &str) -> std::result::Result<Self, String> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let micros = |field: &str| {
+            field
+                .parse::<u64>()
+                .map(Duration::from_micros)
+                .map_err(|_| format!("{} is not a number of microseconds", field))
+        };
+
+        match fields.as_slice() {
+            ["count", mode, count_item] => {
+                Ok(Entry::Count(mode.parse()?, parse_count_item(count_item)?))
+            }
+            ["dwell", key, hold] => {
+                Ok(Entry::Dwell(parse_keycode_from_string(key)?, micros(hold)?))
+            }
+            ["flight", mode, count_item, flight] => Ok(Entry::Flight(
+                mode.parse()?,
+                parse_count_item(count_item)?,
+                micros(flight)?,
+            )),
+            _ => Err(format!("{:?} is not a journal entry", line)),
+        }
+    }
+}
+
+impl KeyCounts {
+    /// Add one journaled increment.
+    pub fn apply(&mut self, entry: &Entry) {
+        match entry {
+            Entry::Count(mode, count_item) => {
+                *self
+                    .counts_mut(*mode)
+                    .entry(count_item.clone())
+                    .or_insert(0) += 1;
+            }
+            Entry::Dwell(key, hold) => self.dwell.entry(*key).or_default().record(*hold),
+            Entry::Flight(mode, count_item, flight) => self
+                .flight
+                .entry(*mode)
+                .or_default()
+                .entry(count_item.clone())
+                .or_default()
+                .record(*flight),
+        }
+    }
+}
+
+/// Path of the journal kept next to the statistics file at `path`.
+pub fn journal_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".journal");
+    path.with_file_name(name)
+}
+
+/// Apply the journal at `path` to `key_counts` unless they already include it.
+///
+/// Returns whether anything was applied. A last line cut short by a crash is skipped.
+pub fn replay(path: &Path, key_counts: &mut KeyCounts) -> Result<bool> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(error) => return Err(Error::io(path, error)),
+    };
+
+    let mut lines = contents.split_inclusive('\n');
+    let generation = match lines.next() {
+        Some(header) if header.ends_with('\n') => header
+            .trim_end()
+            .strip_prefix(HEADER)
+            .and_then(|generation| generation.trim().parse::<u64>().ok())
+            .ok_or_else(|| Error::parse(path, "journal header is missing"))?,
+        // the journal was being created
+        _ => return Ok(false),
+    };
+
+    if generation <= key_counts.journal {
+        return Ok(false);
+    }
+
+    for (index, line) in lines.enumerate() {
+        if !line.ends_with('\n') {
+            log::warn!("{:?}: incomplete last entry skipped", path);
+            break;
+        }
+        let entry = Entry::from_line(line.trim_end_matches('\n'))
+            .map_err(|message| Error::parse(path, format!("line {}: {}", index + 2, message)))?;
+        key_counts.apply(&entry);
+    }
+    key_counts.journal = generation;
+
+    Ok(true)
+}
+
+/// Open journal that entries are appended to.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    generation: u64,
+}
+
+impl Journal {
+    /// Start an empty journal of `generation` at `path`, replacing any previous one.
+    pub fn create(path: &Path, generation: u64) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|error| Error::io(path, error))?;
+
+        let mut journal = Self {
+            path: path.to_path_buf(),
+            file,
+            generation,
+        };
+        journal.write_line(&format!("{} {}", HEADER, generation))?;
+
+        Ok(journal)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn append(&mut self, entry: &Entry) -> Result<()> {
+        self.write_line(&entry.to_line())
+    }
+
+    /// Drop every entry once they are saved in the statistics file,
+    /// starting the next generation.
+    pub fn compact(&mut self) -> Result<()> {
+        *self = Self::create(&self.path, self.generation + 1)?;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        // a single write, so a kill leaves at most one partial line
+        self.file
+            .write_all(format!("{}\n", line).as_bytes())
+            .map_err(|error| Error::io(&self.path, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::Capture;
+    use crate::counts::Config;
+    use crate::source::KeyEvent;
+    use crate::temp::TempFile;
+
+    fn chords() -> Mode {
+        Mode::new(1, true)
+    }
+
+    fn a() -> CountItem {
+        CountItem::Single(vec![Keycode::A])
+    }
+
+    #[test]
+    fn entry_lines() {
+        let entries = [
+            Entry::Count(chords(), a()),
+            Entry::Dwell(Keycode::A, Duration::from_micros(85_000)),
+            Entry::Flight(
+                Mode::new(2, true),
+                CountItem::Pair(vec![Keycode::A], vec![Keycode::B, Keycode::LShift]),
+                Duration::from_micros(120_000),
+            ),
+        ];
+
+        for entry in entries {
+            assert_eq!(Entry::from_line(&entry.to_line()), Ok(entry));
+        }
+        assert!(Entry::from_line("count\tchords").is_err());
+        assert!(Entry::from_line("dwell\tA\t85ms").is_err());
+    }
+
+    #[test]
+    fn replay_skips_partial_last_line() {
+        let path = TempFile::new("partial.journal");
+        fs::write(
+            &path,
+            format!("{HEADER} 1\ncount\tchords\tA\ndwell\tA\t85000\ncount\tchords\tB"),
+        )
+        .unwrap();
+        let mut key_counts = KeyCounts::new();
+
+        assert!(replay(&path, &mut key_counts).unwrap());
+        assert_eq!(key_counts.get(&chords(), &a()), 1);
+        assert_eq!(key_counts.total(&chords()), 1);
+        assert_eq!(key_counts.dwell[&Keycode::A].count, 1);
+        assert_eq!(key_counts.journal, 1);
+    }
+
+    #[test]
+    fn replay_of_partial_header() {
+        let path = TempFile::new("partial-header.journal");
+        fs::write(&path, "hectic-key").unwrap();
+        let mut key_counts = KeyCounts::new();
+
+        assert!(!replay(&path, &mut key_counts).unwrap());
+        assert_eq!(key_counts.journal, 0);
+    }
+
+    #[test]
+    fn replay_reports_bad_line() {
+        let path = TempFile::new("bad.journal");
+        fs::write(
+            &path,
+            format!("{HEADER} 1\ncount\tchords\tA\ncount\tchords\n"),
+        )
+        .unwrap();
+
+        match replay(&path, &mut KeyCounts::new()).unwrap_err() {
+            Error::Parse { message, .. } => assert!(message.starts_with("line 3:"), "{}", message),
+            error => panic!("{:?}", error),
+        }
+    }
+
+    #[test]
+    fn replay_only_newer_generation() {
+        let path = TempFile::new("generation.journal");
+        fs::write(&path, format!("{HEADER} 3\ncount\tchords\tA\n")).unwrap();
+        let mut key_counts = KeyCounts::new();
+        key_counts.journal = 3;
+
+        // the statistic file was saved with every entry of this journal
+        assert!(!replay(&path, &mut key_counts).unwrap());
+        assert_eq!(key_counts.total(&chords()), 0);
+
+        key_counts.journal = 2;
+        assert!(replay(&path, &mut key_counts).unwrap());
+        assert_eq!(key_counts.get(&chords(), &a()), 1);
+        assert_eq!(key_counts.journal, 3);
+    }
+
+    #[test]
+    fn compact_after_save() {
+        let statistic_path = TempFile::new("compact.yaml");
+        let path = TempFile::new("compact.yaml.journal");
+        assert_eq!(journal_path(&statistic_path), path.to_path_buf());
+
+        let mut key_counts = KeyCounts::new();
+        key_counts.set_config(Config::default());
+        let mut capture = Capture::new(key_counts, vec![chords()]);
+        capture.statistic_path = Some(statistic_path.to_path_buf());
+        capture.save_every = u64::MAX;
+        capture.save_interval = Duration::MAX;
+        capture.journal = Some(Journal::create(&path, 1).unwrap());
+        capture
+            .update(vec![
+                KeyEvent::press(Keycode::A, Duration::ZERO),
+                KeyEvent::release(Keycode::A, Duration::from_millis(10)),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{HEADER} 1\ncount\tchords\tA\n")
+        );
+
+        capture.flush().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{HEADER} 2\n"));
+        assert_eq!(capture.journal.as_ref().unwrap().generation(), 2);
+
+        let mut saved = KeyCounts::load(&statistic_path).unwrap();
+        assert_eq!(saved.journal, 1);
+        assert!(replay(&path, &mut saved).unwrap());
+        assert_eq!(saved.get(&chords(), &a()), 1);
+    }
+}
@@ -0,0 +1,147 @@
+//! Mapping between [`Keycode`]s and Linux kernel key codes.
+
+use device_query::Keycode;
+
+/// Kernel key codes (`input-event-codes.h`) that have a [`Keycode`].
+///
+/// Sorted by kernel code, when a kernel code has no own [`Keycode`]
+/// it shares one with a similar key (e.g. keypad enter).
+pub const KERNEL_KEYCODES: &[(u16, Keycode)] = &[
+    (1, Keycode::Escape),
+    (2, Keycode::Key1),
+    (3, Keycode::Key2),
+    (4, Keycode::Key3),
+    (5, Keycode::Key4),
+    (6, Keycode::Key5),
+    (7, Keycode::Key6),
+    (8, Keycode::Key7),
+    (9, Keycode::Key8),
+    (10, Keycode::Key9),
+    (11, Keycode::Key0),
+    (12, Keycode::Minus),
+    (13, Keycode::Equal),
+    (14, Keycode::Backspace),
+    (15, Keycode::Tab),
+    (16, Keycode::Q),
+    (17, Keycode::W),
+    (18, Keycode::E),
+    (19, Keycode::R),
+    (20, Keycode::T),
+    (21, Keycode::Y),
+    (22, Keycode::U),
+    (23, Keycode::I),
+    (24, Keycode::O),
+    (25, Keycode::P),
+    (26, Keycode::LeftBracket),
+    (27, Keycode::RightBracket),
+    (28, Keycode::Enter),
+    (29, Keycode::LControl),
+    (30, Keycode::A),
+    (31, Keycode::S),
+    (32, Keycode::D),
+    (33, Keycode::F),
+    (34, Keycode::G),
+    (35, Keycode::H),
+    (36, Keycode::J),
+    (37, Keycode::K),
+    (38, Keycode::L),
+    (39, Keycode::Semicolon),
+    (40, Keycode::Apostrophe),
+    (41, Keycode::Grave),
+    (42, Keycode::LShift),
+    (43, Keycode::BackSlash),
+    (44, Keycode::Z),
+    (45, Keycode::X),
+    (46, Keycode::C),
+    (47, Keycode::V),
+    (48, Keycode::B),
+    (49, Keycode::N),
+    (50, Keycode::M),
+    (51, Keycode::Comma),
+    (52, Keycode::Dot),
+    (53, Keycode::Slash),
+    (54, Keycode::RShift),
+    (55, Keycode::NumpadMultiply),
+    (56, Keycode::LAlt),
+    (57, Keycode::Space),
+    (58, Keycode::CapsLock),
+    (59, Keycode::F1),
+    (60, Keycode::F2),
+    (61, Keycode::F3),
+    (62, Keycode::F4),
+    (63, Keycode::F5),
+    (64, Keycode::F6),
+    (65, Keycode::F7),
+    (66, Keycode::F8),
+    (67, Keycode::F9),
+    (68, Keycode::F10),
+    (71, Keycode::Numpad7),
+    (72, Keycode::Numpad8),
+    (73, Keycode::Numpad9),
+    (74, Keycode::NumpadSubtract),
+    (75, Keycode::Numpad4),
+    (76, Keycode::Numpad5),
+    (77, Keycode::Numpad6),
+    (78, Keycode::NumpadAdd),
+    (79, Keycode::Numpad1),
+    (80, Keycode::Numpad2),
+    (81, Keycode::Numpad3),
+    (82, Keycode::Numpad0),
+    (87, Keycode::F11),
+    (88, Keycode::F12),
+    (96, Keycode::Enter),
+    (97, Keycode::RControl),
+    (98, Keycode::NumpadDivide),
+    (100, Keycode::RAlt),
+    (102, Keycode::Home),
+    (103, Keycode::Up),
+    (104, Keycode::PageUp),
+    (105, Keycode::Left),
+    (106, Keycode::Right),
+    (107, Keycode::End),
+    (108, Keycode::Down),
+    (109, Keycode::PageDown),
+    (110, Keycode::Insert),
+    (111, Keycode::Delete),
+    (125, Keycode::LMeta),
+    (126, Keycode::RMeta),
+];
+
+pub fn keycode_from_kernel(code: u16) -> Option<Keycode> {
+    KERNEL_KEYCODES
+        .binary_search_by_key(&code, |(kernel, _)| *kernel)
+        .ok()
+        .map(|index| KERNEL_KEYCODES[index].1)
+}
+
+/// Kernel key code of `key`, `None` for keys that do not exist on Linux.
+pub fn kernel_code(key: &Keycode) -> Option<u16> {
+    KERNEL_KEYCODES
+        .iter()
+        .find(|(_, keycode)| keycode == key)
+        .map(|(kernel, _)| *kernel)
+}
+
+/// Sort keys of a chord by kernel key code, the order `device_query` reports them in,
+/// so the same chord is always written the same way whatever order it was pressed in.
+pub fn sort_keys(keys: &mut [Keycode]) {
+    keys.sort_by_key(|key| kernel_code(key).unwrap_or(u16::MAX));
+}
+
+/// Whether `key` changes what other keys do rather than typing itself.
+pub fn is_modifier(key: &Keycode) -> bool {
+    matches!(
+        key,
+        Keycode::LControl
+            | Keycode::RControl
+            | Keycode::LShift
+            | Keycode::RShift
+            | Keycode::LAlt
+            | Keycode::RAlt
+            | Keycode::Command
+            | Keycode::LOption
+            | Keycode::ROption
+            | Keycode::LMeta
+            | Keycode::RMeta
+    )
+}
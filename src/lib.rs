@@ -0,0 +1,55 @@
+//! Capture keyboard usage statistics and work with the files they are saved in.
+//!
+//! [`KeyCounts`] is the content of a statistics file: it can be loaded,
+//! merged with another one, queried and written back.
+//!
+//! ```
+//! use hectic_key_capture::{CountItem, KeyCounts, Mode};
+//! use device_query::Keycode;
+//!
+//! let mut key_counts = KeyCounts::from_yaml(
+//!     "config:\n  pairs: false\n  no_chords: false\n  version: 1.1.0\nA: 2\nA+LShift: 1\n",
+//! )
+//! .unwrap();
+//! let other = KeyCounts::from_yaml("A: 3\n").unwrap();
+//! key_counts.merge(other).unwrap();
+//!
+//! let chords = Mode::new(1, true);
+//! assert_eq!(key_counts.get(&chords, &CountItem::Single(vec![Keycode::A])), 5);
+//! assert_eq!(key_counts.total(&chords), 6);
+//! ```
+//!
+//! Live capture feeds events of a [`source::KeySource`] through [`capture::Capture`].
+
+pub mod binary;
+pub mod capture;
+pub mod counts;
+pub mod diff;
+pub mod error;
+#[cfg(target_os = "linux")]
+pub mod evdev;
+pub mod export;
+pub mod format;
+pub mod heatmap;
+pub mod journal;
+pub mod keycode;
+pub mod persist;
+pub mod replay;
+pub mod report;
+pub mod source;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stats;
+#[cfg(test)]
+mod temp;
+pub mod trace;
+
+pub use counts::{
+    count_item_to_string, input_to_string, keycode_to_string, parse_count_item,
+    parse_input_from_string, parse_keycode_from_string, Config, CountItem, HashMap, KeyCounts,
+    Mode,
+};
+pub use error::{Error, Result};
+pub use format::Format;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -1,43 +1,53 @@
-use ahash::AHasher;
 use colored::*;
-use core::fmt;
 use crossterm::event::{poll, KeyModifiers};
 use crossterm::{
     event::{read, Event, KeyCode},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use device_query::{DeviceQuery, DeviceState, Keycode};
-use serde::de::{self, MapAccess, Visitor};
-use serde::ser::SerializeMap;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use serde_derive::{Deserialize, Serialize};
-use std::fs::{File, OpenOptions};
-use std::hash::BuildHasherDefault;
-use std::io::{self, stdout, Read, Write};
-use std::ops::{Deref, DerefMut};
+use hectic_key_capture::capture::{Capture, DEFAULT_SAVE_EVERY, DEFAULT_SAVE_INTERVAL};
+use hectic_key_capture::counts::{
+    count_item_to_string, keycode_to_string, Order, CHORDS_SECTION, CHORD_PAIRS_SECTION,
+    COUNT_ORDER, KEYS_SECTION, KEY_ORDER, KEY_PAIRS_SECTION,
+};
+use hectic_key_capture::diff::{self, Change, SectionDiff};
+use hectic_key_capture::error::Setting;
+use hectic_key_capture::export::{self, CSV_DELIMITER, TSV_DELIMITER};
+#[cfg(feature = "sqlite")]
+use hectic_key_capture::format::SQLITE_FORMAT;
+use hectic_key_capture::format::{BINARY_FORMAT, JSON_FORMAT, YAML_FORMAT};
+use hectic_key_capture::heatmap::{
+    self, Heatmap, Layout, ANSI_LAYOUT, ISO_LAYOUT, ORTHO_LAYOUT, SPLIT_LAYOUT,
+};
+use hectic_key_capture::journal::{self, Journal};
+use hectic_key_capture::persist;
+use hectic_key_capture::replay;
+use hectic_key_capture::report;
+use hectic_key_capture::source::{Backend, DeviceQuerySource, KeySource};
+#[cfg(feature = "sqlite")]
+use hectic_key_capture::sqlite::Database;
+use hectic_key_capture::stats::HISTOGRAM_BOUNDS_MS;
+use hectic_key_capture::trace::{
+    upend_trace, TraceFormat, TraceStep, JSON_TRACE_FORMAT, PLAIN_TRACE_FORMAT, TEXT_TRACE_FORMAT,
+};
+use hectic_key_capture::{Config, Error, Format, KeyCounts, Mode, Result, VERSION};
+#[cfg(unix)]
+use signal_hook::consts::SIGHUP;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, stdout, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
-use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
-use std::{env, thread};
-
-/// A [`HashMap`][hashbrown::HashMap] implementing aHash, a high
-/// speed keyed hashing algorithm intended for use in in-memory hashmaps.
-///
-/// aHash is designed for performance and is NOT cryptographically secure.
-///
-/// Within the same execution of the program iteration order of different
-/// `HashMap`s only depends on the order of insertions and deletions,
-/// but it will not be stable between multiple executions of the program.
-pub type HashMap<K, V> = hashbrown::HashMap<K, V, BuildHasherDefault<AHasher>>;
 
 const PRODUCTIVE_SENSITIVITY_KEY: &str = "productive";
 const PRODUCTIVE_SENSITIVITY_VALUE: u64 = 100;
 const INTENT_SENSITIVITY_KEY: &str = "intent";
 const INTENT_SENSITIVITY_VALUE: u64 = 1;
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
-
 const SENSITIVITY_SHORT: &str = "-s";
 const SENSITIVITY_LONG: &str = "--sensitivity";
 
@@ -67,217 +77,128 @@ const TRACE_LONG: &str = "--trace";
 const PAIRS_SHORT: &str = "-p";
 const PAIRS_LONG: &str = "--pairs";
 
+const NGRAM_LONG: &str = "--ngram";
+
+const COLLECT_SHORT: &str = "-c";
+const COLLECT_LONG: &str = "--collect";
+
 const PLAIN_SHORT: &str = "-P";
 const PLAIN_LONG: &str = "--plain-style";
 
-const NO_CHORDS_LONG: &str = "--no-chords";
-
-const DEFAULT_STATISTIC_PATH_YAML: &str = "key-capture-statistic.yaml";
+const TRACE_FORMAT_LONG: &str = "--trace-format";
 
-macro_rules! verbose {
-    ($verbose:expr, $($arg:tt)*) => {
-        if $verbose {
-            println!("\r{}", format!($($arg)*));
-        }
-    };
-}
+const NO_CHORDS_LONG: &str = "--no-chords";
 
-trait Frmater {
-    fn to_string(&self) -> &str;
-}
+const DWELL_LONG: &str = "--dwell";
 
-impl Frmater for PathBuf {
-    fn to_string(&self) -> &str {
-        if let Some(str) = self.to_str() {
-            str
-        } else {
-            "%path error%"
-        }
-    }
-}
+const FLIGHT_LONG: &str = "--flight";
 
-#[derive(Debug)]
-pub enum TraceStep {
-    First(Vec<Keycode>),
-    Regular(Vec<Keycode>, Duration),
-    Empty,
-}
+const FORMAT_SHORT: &str = "-f";
+const FORMAT_LONG: &str = "--format";
 
-#[derive(Debug)]
-pub struct KeyCounts {
-    pub config: Option<Config>,
-    pub map: HashMap<CountItem, u32>,
-}
+const FROM_LONG: &str = "--from";
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
-pub struct Config {
-    pub pairs: bool,
-    pub no_chords: bool,
-    pub version: String,
-}
+const JOURNAL_LONG: &str = "--journal";
 
-impl Config {
-    pub fn new(pairs: bool, no_chords: bool, version: String) -> Self {
-        Self {
-            pairs,
-            no_chords,
-            version,
-        }
-    }
-}
+const BACKUPS_LONG: &str = "--backups";
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone)]
-pub enum CountItem {
-    Single(Vec<Keycode>),
-    Pair(Vec<Keycode>, Vec<Keycode>),
-}
+const SAVE_INTERVAL_LONG: &str = "--save-interval";
 
-fn keycode_to_string(keycode: &Keycode) -> String {
-    format!("{:?}", keycode)
-}
+const SAVE_EVERY_LONG: &str = "--save-every";
 
-fn input_to_string(input: Vec<Keycode>) -> String {
-    let keys_str: Vec<String> = input.iter().map(keycode_to_string).collect();
-    format!("{:?}", keys_str.join("+")).replace("\"", "")
-}
+const BACKEND_SHORT: &str = "-b";
+const BACKEND_LONG: &str = "--backend";
 
-impl Serialize for KeyCounts {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut map = serializer.serialize_map(Some(self.map.len()))?;
+const DEVICE_QUERY_BACKEND_KEY: &str = "device-query";
+const EVDEV_BACKEND_KEY: &str = "evdev";
 
-        map.serialize_entry("config", &self.config)?;
+const DEVICE_SHORT: &str = "-d";
+const DEVICE_LONG: &str = "--device";
 
-        for (key, value) in &self.map {
-            let key_str = match key {
-                CountItem::Single(input) => {
-                    let input_str: String = input_to_string(input.clone());
-                    format!("{}", input_str)
-                }
-                CountItem::Pair(input1, input2) => {
-                    let input1_str: String = input_to_string(input1.clone());
-                    let input2_str: String = input_to_string(input2.clone());
-                    format!("{}, {}", input1_str, input2_str)
-                }
-            };
-            map.serialize_entry(&key_str, value)?;
-        }
+const EXIT_ABORTED: i32 = 0;
+const EXIT_IO: i32 = 1;
+const EXIT_PARSE: i32 = 2;
+const EXIT_CONFIG_MISMATCH: i32 = 3;
+const EXIT_SIGNAL: i32 = 130;
 
-        map.end()
-    }
-}
+const CONVERT_COMMAND: &str = "convert";
+const EXPORT_COMMAND: &str = "export";
+const REPLAY_COMMAND: &str = "replay";
+const MERGE_COMMAND: &str = "merge";
+const DIFF_COMMAND: &str = "diff";
+const REPORT_COMMAND: &str = "report";
+const HEATMAP_COMMAND: &str = "heatmap";
 
-fn parse_keycode_from_string(s: &str) -> Result<Keycode, String> {
-    log::trace!("try to parse keycode from {}", s);
-    Keycode::from_str(s)
-}
+const SECTION_LONG: &str = "--section";
+const TSV_LONG: &str = "--tsv";
+const CONVERT_LONG: &str = "--convert";
+const TOP_LONG: &str = "--top";
+const JSON_LONG: &str = "--json";
+const SORT_LONG: &str = "--sort";
+const LAYOUT_LONG: &str = "--layout";
+const LAYOUT_FILE_LONG: &str = "--layout-file";
 
-fn parse_input_from_string(s: &str) -> Result<Vec<Keycode>, String> {
-    let keycodes = s
-        .split('+')
-        .filter_map(|s| {
-            let trimmed = s.trim();
-            if trimmed.is_empty() {
-                None
-            } else {
-                Some(parse_keycode_from_string(trimmed))
-            }
-        })
-        .collect::<Result<Vec<Keycode>, String>>()?;
+const DEFAULT_TOP: usize = 10;
 
-    Ok(keycodes)
+const DEFAULT_STATISTIC_PATH_YAML: &str = "key-capture-statistic.yaml";
+const DEFAULT_STATISTIC_PATH_JSON: &str = "key-capture-statistic.json";
+
+#[cfg(feature = "sqlite")]
+const SQLITE_NOTE: &str = "sqlite keeps statistic and every capture session
+                    in one database (.sqlite, .sqlite3, .db)";
+#[cfg(not(feature = "sqlite"))]
+const SQLITE_NOTE: &str = "sqlite is available when built with --features sqlite";
+#[cfg(feature = "sqlite")]
+const TRACE_SQLITE_NOTE: &str = ",
+                    into trace table of database for .sqlite, .sqlite3, .db
+                    where every capture session is kept";
+#[cfg(not(feature = "sqlite"))]
+const TRACE_SQLITE_NOTE: &str = "";
+
+/// Whether counts logged by capture are printed, see [`Logger`].
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Logger configured by `RUST_LOG`, that also prints counts logged by capture with `--verbose`.
+struct Logger {
+    env: env_logger::Logger,
 }
 
-fn parse_count_item(s: &str) -> Result<CountItem, String> {
-    let inputs = s
-        .split(", ")
-        .map(parse_input_from_string)
-        .collect::<Result<Vec<Vec<Keycode>>, String>>()?;
-
-    if inputs.len() == 1 {
-        Ok(CountItem::Single(inputs[0].clone()))
-    } else if inputs.len() > 2 {
-        Err("Unrecognized CountItem format".to_string())
-    } else {
-        Ok(CountItem::Pair(inputs[0].clone(), inputs[1].clone()))
+impl Logger {
+    fn init() {
+        let env = env_logger::Builder::from_default_env().build();
+        log::set_max_level(env.filter());
+        log::set_boxed_logger(Box::new(Logger { env })).expect("logger is set once");
     }
-}
-
-impl<'de> Deserialize<'de> for KeyCounts {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        struct KeyCountsVisitor;
-
-        impl<'de> Visitor<'de> for KeyCountsVisitor {
-            type Value = KeyCounts;
-
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("a map of CountItem to u32")
-            }
-
-            fn visit_map<M>(self, mut map: M) -> Result<KeyCounts, M::Error>
-            where
-                M: MapAccess<'de>,
-            {
-                let mut counts = HashMap::new();
-                let mut config = None;
-
-                while let Some((key_str, value)) = map.next_entry::<String, serde_yaml::Value>()? {
-                    match key_str.as_str() {
-                        "config" => {
-                            config =
-                                Some(serde_yaml::from_value(value).map_err(de::Error::custom)?);
-                        }
-                        _ => {
-                            let count_item =
-                                parse_count_item(&key_str).map_err(de::Error::custom)?;
-                            counts.insert(
-                                count_item,
-                                value
-                                    .as_u64()
-                                    .ok_or_else(|| de::Error::custom("Expected u64 value"))?
-                                    as u32,
-                            );
-                        }
-                    }
-                }
 
-                let config = config.unwrap_or_default();
-                Ok(KeyCounts {
-                    config,
-                    map: counts,
-                })
-            }
-        }
-        deserializer.deserialize_map(KeyCountsVisitor)
+    fn is_verbose(metadata: &log::Metadata) -> bool {
+        VERBOSE.load(Ordering::Relaxed)
+            && metadata.level() <= log::Level::Info
+            && metadata.target() == "hectic_key_capture::capture"
     }
 }
 
-impl Deref for KeyCounts {
-    type Target = HashMap<CountItem, u32>;
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        Self::is_verbose(metadata) || self.env.enabled(metadata)
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.map
+    fn log(&self, record: &log::Record) {
+        if Self::is_verbose(record.metadata()) {
+            println!("\r{}", record.args());
+        } else if self.env.matches(record) {
+            self.env.log(record);
+        }
     }
-}
 
-impl DerefMut for KeyCounts {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.map
+    fn flush(&self) {
+        self.env.flush();
     }
 }
 
 fn main() {
-    env_logger::init();
+    Logger::init();
 
-    let mut key_counts = KeyCounts {
-        config: None,
-        map: HashMap::new(),
-    };
+    let mut key_counts = KeyCounts::new();
 
     let mut sensitivity = PRODUCTIVE_SENSITIVITY_VALUE;
     let mut force_modify_output = false;
@@ -285,36 +206,76 @@ fn main() {
     let mut no_output = false;
     let mut verbose = false;
     let mut no_chords = false;
+    let mut dwell = false;
+    let mut flight = false;
     let mut statistic_path: Option<PathBuf> = None;
     let mut trace_path: Option<PathBuf> = None;
-    let mut pairs = false;
-    let mut trace_plain_style = false;
-    let mut first_trace_step = true;
+    let mut ngram = 1;
+    let mut collect: Vec<Mode> = Vec::new();
+    let mut trace_format: Option<TraceFormat> = None;
+    let mut backend = Backend::default();
+    let mut devices: Vec<PathBuf> = Vec::new();
+    let mut save_interval = DEFAULT_SAVE_INTERVAL;
+    let mut save_every = DEFAULT_SAVE_EVERY;
+    let mut backups = 0;
+    let mut use_journal = false;
+    let mut format: Option<Format> = None;
+    let mut order = Order::default();
 
     let mut args = env::args();
     let program_name = args.next().expect("this panic not posible");
 
+    let mut args = args.peekable();
+    match args.peek().map(String::as_str) {
+        Some(CONVERT_COMMAND) => {
+            args.next();
+            convert(&program_name, args);
+            return;
+        }
+        Some(EXPORT_COMMAND) => {
+            args.next();
+            export(&program_name, args);
+            return;
+        }
+        Some(REPLAY_COMMAND) => {
+            args.next();
+            replay(&program_name, args);
+            return;
+        }
+        Some(MERGE_COMMAND) => {
+            args.next();
+            merge(&program_name, args);
+            return;
+        }
+        Some(DIFF_COMMAND) => {
+            args.next();
+            diff(&program_name, args);
+            return;
+        }
+        Some(REPORT_COMMAND) => {
+            args.next();
+            report(&program_name, args);
+            return;
+        }
+        Some(HEATMAP_COMMAND) => {
+            args.next();
+            heatmap(&program_name, args);
+            return;
+        }
+        _ => {}
+    }
+
     while let Some(arg) = args.next() {
         match arg.as_str() {
             SENSITIVITY_SHORT | SENSITIVITY_LONG => {
-                sensitivity = match args
-                    .next()
-                    .or_else(|| {
-                        println!(
-                            "{} {arg} {}",
-                            "error: necessary value for option".red(),
-                            "(sensitivity)".red()
-                        );
-                        std::process::exit(1);
-                    })
-                    .unwrap()
+                sensitivity = match option_value(&mut args, &arg, "sensitivity")
                     .as_str()
                 {
                     PRODUCTIVE_SENSITIVITY_KEY => PRODUCTIVE_SENSITIVITY_VALUE,
                     INTENT_SENSITIVITY_KEY => INTENT_SENSITIVITY_VALUE,
                     other => other
                         .parse::<u64>()
-                        .or_else(|_| -> Result<_, ()> {
+                        .map_err(|_| {
                             println!(
                                 "{} {other} {}\n{} number > 0 {or} {PRODUCTIVE_SENSITIVITY_KEY} {or} {INTENT_SENSITIVITY_KEY}",
                                 "error:".red(),
@@ -334,51 +295,151 @@ fn main() {
             MODIFY_OUTPUT_SHORT | MODIFY_OUTPUT_LONG => {
                 force_modify_output = true;
             }
-            PAIRS_SHORT | PAIRS_LONG => pairs = true,
-            OUTPUT_SHORT | OUTPUT_LONG => {
-                let path = args
-                    .next()
-                    .or_else(|| {
+            PAIRS_SHORT | PAIRS_LONG => ngram = 2,
+            NGRAM_LONG => {
+                let value = option_value(&mut args, &arg, "ngram");
+
+                ngram = parse_ngram(&value);
+            }
+            FORMAT_SHORT | FORMAT_LONG => {
+                format = Some(parse_format(&option_value(&mut args, &arg, "format")));
+            }
+            SORT_LONG => order = parse_order(&option_value(&mut args, &arg, "sort")),
+            BACKUPS_LONG => {
+                let value = option_value(&mut args, &arg, "backups");
+
+                backups = value
+                    .parse::<usize>()
+                    .map_err(|_| {
                         println!(
-                            "{} {arg} {}",
-                            "error: necessary value for option".red(),
-                            "(output)".red()
+                            "{} {value} {}\n{}",
+                            "error:".red(),
+                            "is not valid value for backups".red(),
+                            "must be a number".red(),
                         );
                         std::process::exit(1);
                     })
                     .unwrap();
+            }
+            SAVE_INTERVAL_LONG => {
+                let value = option_value(&mut args, &arg, "save interval");
+
+                save_interval = match value.parse::<u64>() {
+                    Ok(seconds) => Duration::from_secs(seconds),
+                    _ => {
+                        println!(
+                            "{} {value} {}\n{}",
+                            "error:".red(),
+                            "is not valid value for save interval".red(),
+                            "must be a number of seconds".red(),
+                        );
+                        std::process::exit(1);
+                    }
+                };
+            }
+            SAVE_EVERY_LONG => {
+                let value = option_value(&mut args, &arg, "save every");
 
-                let path = Path::new(&path);
-                statistic_path = Some(path.to_path_buf());
+                save_every = match value.parse::<u64>() {
+                    Ok(save_every) if save_every > 0 => save_every,
+                    _ => {
+                        println!(
+                            "{} {value} {}\n{}",
+                            "error:".red(),
+                            "is not valid value for save every".red(),
+                            "must be a number > 0".red(),
+                        );
+                        std::process::exit(1);
+                    }
+                };
+            }
+            OUTPUT_SHORT | OUTPUT_LONG => {
+                statistic_path = Some(PathBuf::from(option_value(&mut args, &arg, "output")));
             }
             MODIFY_TRACE_SHORT | MODIFY_TRACE_LONG => {
                 force_modify_trace = true;
             }
             TRACE_SHORT | TRACE_LONG => {
-                let path = args
-                    .next()
-                    .or_else(|| {
+                trace_path = Some(PathBuf::from(option_value(&mut args, &arg, "trace")));
+            }
+            BACKEND_SHORT | BACKEND_LONG => {
+                backend = match option_value(&mut args, &arg, "backend")
+                    .as_str()
+                {
+                    DEVICE_QUERY_BACKEND_KEY => Backend::DeviceQuery,
+                    EVDEV_BACKEND_KEY => Backend::Evdev,
+                    other => {
                         println!(
-                            "{} {arg} {}",
-                            "error: necessary value for option".red(),
-                            "(trace)".red()
+                            "{} {other} {}\n{} {DEVICE_QUERY_BACKEND_KEY} {or} {EVDEV_BACKEND_KEY}",
+                            "error:".red(),
+                            "is not valid value for backend".red(),
+                            "must be".red(),
+                            or = "or".red(),
                         );
                         std::process::exit(1);
-                    })
-                    .unwrap();
+                    }
+                }
+            }
+            DEVICE_SHORT | DEVICE_LONG => {
+                devices.push(PathBuf::from(option_value(&mut args, &arg, "device")));
+            }
+            COLLECT_SHORT | COLLECT_LONG => {
+                let value = option_value(&mut args, &arg, "collect");
 
-                let path = Path::new(&path);
-                trace_path = Some(path.to_path_buf());
+                collect.extend(parse_collect(&value));
             }
             NO_CHORDS_LONG => no_chords = true,
+            DWELL_LONG => dwell = true,
+            FLIGHT_LONG => flight = true,
+            JOURNAL_LONG => use_journal = true,
             NO_OUTPUT_LONG => no_output = true,
-            PLAIN_SHORT | PLAIN_LONG => trace_plain_style = true,
+            PLAIN_SHORT | PLAIN_LONG => trace_format = Some(TraceFormat::Plain),
+            TRACE_FORMAT_LONG => {
+                let value = option_value(&mut args, &arg, "trace format");
+                trace_format = Some(parse_trace_format(&value));
+            }
             VERBOSE_SHORT | VERBOSE_LONG => verbose = true,
             HELP_SHORT | "-?" | "?" | "h" | HELP_LONG | "-help" | "help" => {
                 println!(
                     r#"Program for capture statistic of you keyboard usage
 
 {usage_title} {usage_content}
+       {convert_usage}
+       {export_usage}
+       {replay_usage}
+       {merge_usage}
+       {diff_usage}
+       {report_usage}
+       {heatmap_usage}
+
+{commands_title}
+    {convert_command}
+                    Convert statistic file to another format,
+                    see {convert_command} {help_long}
+
+    {export_command}
+                    Write counts as CSV or TSV table,
+                    see {export_command} {help_long}
+
+    {replay_command}
+                    Count a trace file again under any sections,
+                    see {replay_command} {help_long}
+
+    {merge_command}
+                    Sum several statistic files into one,
+                    see {merge_command} {help_long}
+
+    {diff_command}
+                    Compare two statistic files,
+                    see {diff_command} {help_long}
+
+    {report_command}
+                    Print the most frequent items with their shares,
+                    see {report_command} {help_long}
+
+    {heatmap_command}
+                    Draw a keyboard shaded by how often each key is pressed,
+                    see {heatmap_command} {help_long}
 
 {optiongs_title}
     {sensitivity_short}, {sensitivity_long} {sensitivity_value}
@@ -396,19 +457,110 @@ fn main() {
 
                     {default} {PRODUCTIVE_SENSITIVITY_VALUE}
 
+    {backend_short}, {backend_long} {backend_value}
+                    Where keyboard input is taken from
+
+                    {DEVICE_QUERY_BACKEND_KEY} - poll pressed keys every sensitivity
+                        milliseconds, needs X session on Linux
+                    {EVDEV_BACKEND_KEY} - read press and release events from /dev/input,
+                        no keystroke is missed, works on Wayland and TTY,
+                        needs read access to event devices (root or input group)
+
+                    {default} {DEVICE_QUERY_BACKEND_KEY}
+
+    {device_short}, {device_long} {device_value}
+                    Event device or file with recorded events for {EVDEV_BACKEND_KEY} backend,
+                    can be repeated
+
+                    {default} all keyboards found in /dev/input
+
     {pairs_short}, {pairs_long} 
-                    Save buttons pairs counts instead single
+                    Save buttons pairs counts instead single,
+                    same as {ngram_long} 2
+
+    {ngram_long} {ngram_value}
+                    Save counts of n inputs pressed one after another,
+                    1 - singles, 2 - pairs, 3 - trigrams and so on
+
+                    {default} 1
+
+    {collect_short}, {collect_long} {collect_value}
+                    Collect several kinds of counts at once,
+                    each one in its own section of output file:
+
+                    {CHORDS_SECTION} - keys pressed together as one input
+                    {KEYS_SECTION} - every key separately
+                    {CHORD_PAIRS_SECTION}, {KEY_PAIRS_SECTION} - pairs of such inputs
+                    chord-<n>grams, key-<n>grams - n such inputs in a row
+
+                    Replaces {pairs_short}, {pairs_long}, {ngram_long} and {no_chords_long}
 
     {no_chords_long}
                     Get inputs separately not paying attention to simultaneous presses
                     
+    {dwell_long}
+                    Also save how long each key is held down
+                    (count, mean, min, max and histogram in milliseconds)
+                    under dwell section of output file
+
+                    Histogram buckets end at {histogram_bounds} ms,
+                    the last one collects everything longer
+
+    {flight_long}
+                    When pairs are counted, by {pairs_short}, {pairs_long} or a
+                    {CHORD_PAIRS_SECTION} or {KEY_PAIRS_SECTION} section of {collect_long},
+                    also save time between the inputs of each pair (count, mean,
+                    variance, percentiles, min, max and histogram in milliseconds)
+                    under flight section of output file
+
     {modify_output_short}, {modify_output_long}         
                     Force modify output file if it already exists
 
     {output_short}, {output_long} {output_value}         
                     Output file
 
-                    {default} {DEFAULT_STATISTIC_PATH_YAML}
+                    {default} {DEFAULT_STATISTIC_PATH_YAML} ({DEFAULT_STATISTIC_PATH_JSON} for {JSON_FORMAT} format)
+
+    {save_interval_long} {save_interval_value}
+                    Save output file at least that often (seconds)
+                    while there are new counts, and when capture stops
+
+                    {default} {default_save_interval}
+
+    {save_every_long} {save_every_value}
+                    Save output file once that many counts are new
+
+                    {default} {DEFAULT_SAVE_EVERY}
+
+    {journal_long}
+                    Also append every count to <path>.journal at once,
+                    so nothing is lost when capture is killed between saves;
+                    a journal left behind is applied on next start
+
+    {backups_long} {backups_value}
+                    Keep that many previous versions of output file
+                    as <path>.1 (the newest) to <path>.<n>,
+                    rotated every time capture starts
+
+                    {default} 0
+
+    {format_short}, {format_long} {format_value}
+                    Format of output file, {BINARY_FORMAT} is compact and fast to load,
+                    {convert_command} turns it into {YAML_FORMAT} to read it
+
+                    {default} by extension of output file (.yaml, .yml, .json, .hkcs),
+                    {YAML_FORMAT} if unknown
+
+                    {sqlite_note}
+
+    {sort_long} {sort_value}
+                    Order counts are written in, so a file kept in version control
+                    only changes where counts do
+
+                    {KEY_ORDER} - by key
+                    {COUNT_ORDER} - most frequent first
+
+                    {default} {KEY_ORDER}
 
     {no_output_long}
                     Does not create an output file.
@@ -416,7 +568,7 @@ fn main() {
 
     {trace_short}, {trace_long} {trace_value}
                     Save trace (Key, Duratin) in file
-                    where Duration is time between curent and last key pressed
+                    where Duration is time between curent and last key pressed{trace_sqlite_note}
 
     {modify_trace_short}, {modify_trace_long}
                     Force modify trace file if it already exists
@@ -429,11 +581,40 @@ fn main() {
 
     {plain_short}, {plain_long}
                     Trace output in postscript plain style.
+                    Same as {trace_format_long} {PLAIN_TRACE_FORMAT}
+
+    {trace_format_long} {trace_format_value}
+                    Format of trace file
+
+                    {TEXT_TRACE_FORMAT} - "<keys> after <duration>" lines
+                    {PLAIN_TRACE_FORMAT} - keys separated by spaces
+                    {JSON_TRACE_FORMAT} - JSON Lines, one object per press and release
+                        with event, keys, time_ms (since first event, by the clock
+                        of the backend), wall_ms (Unix time the event was handled at)
+                        and delta_ms (since previous event)
+
+                    {default} by extension of trace file ({JSON_TRACE_FORMAT} for .jsonl, .ndjson),
+                    {TEXT_TRACE_FORMAT} if unknown
 
     {help_short}, {help_long}         
                     This message"#,
                     usage_title = "Usage:".green(),
                     optiongs_title = "Options:".green(),
+                    commands_title = "Commands:".green(),
+                    convert_command = CONVERT_COMMAND.cyan(),
+                    convert_usage = format!("{program_name} {CONVERT_COMMAND} [OPTIONS] <input> <output>").cyan(),
+                    export_command = EXPORT_COMMAND.cyan(),
+                    export_usage = format!("{program_name} {EXPORT_COMMAND} [OPTIONS] <input>").cyan(),
+                    replay_command = REPLAY_COMMAND.cyan(),
+                    replay_usage = format!("{program_name} {REPLAY_COMMAND} [OPTIONS] <trace> <output>").cyan(),
+                    merge_command = MERGE_COMMAND.cyan(),
+                    merge_usage = format!("{program_name} {MERGE_COMMAND} [OPTIONS] {OUTPUT_SHORT} <output> <input>...").cyan(),
+                    diff_command = DIFF_COMMAND.cyan(),
+                    diff_usage = format!("{program_name} {DIFF_COMMAND} [OPTIONS] <before> <after>").cyan(),
+                    report_command = REPORT_COMMAND.cyan(),
+                    report_usage = format!("{program_name} {REPORT_COMMAND} [OPTIONS] <input>").cyan(),
+                    heatmap_command = HEATMAP_COMMAND.cyan(),
+                    heatmap_usage = format!("{program_name} {HEATMAP_COMMAND} [OPTIONS] <input>").cyan(),
                     usage_content = format!("{program_name} [OPTIONS]").cyan(),
                     sensitivity_short = SENSITIVITY_SHORT.cyan(),
                     sensitivity_long = SENSITIVITY_LONG.cyan(),
@@ -456,14 +637,47 @@ fn main() {
                     trace_short = TRACE_SHORT.cyan(),
                     trace_long = TRACE_LONG.cyan(),
                     trace_value = "<path>".cyan(),
+                    backend_short = BACKEND_SHORT.cyan(),
+                    backend_long = BACKEND_LONG.cyan(),
+                    backend_value = format!("[{DEVICE_QUERY_BACKEND_KEY} | {EVDEV_BACKEND_KEY}]").cyan(),
+                    device_short = DEVICE_SHORT.cyan(),
+                    device_long = DEVICE_LONG.cyan(),
+                    device_value = "<path>".cyan(),
                     pairs_short = PAIRS_SHORT.cyan(),
                     pairs_long = PAIRS_LONG.cyan(),
+                    ngram_long = NGRAM_LONG.cyan(),
+                    ngram_value = "<n>".cyan(),
                     no_chords_long = NO_CHORDS_LONG.cyan(),
+                    collect_short = COLLECT_SHORT.cyan(),
+                    collect_long = COLLECT_LONG.cyan(),
+                    collect_value = "<section>[,<section>...]".cyan(),
+                    dwell_long = DWELL_LONG.cyan(),
+                    flight_long = FLIGHT_LONG.cyan(),
+                    histogram_bounds = HISTOGRAM_BOUNDS_MS.map(|bound| bound.to_string()).join(", "),
                     modify_trace_short = MODIFY_TRACE_SHORT.cyan(),
                     modify_trace_long = MODIFY_TRACE_LONG.cyan(),
                     plain_short = PLAIN_SHORT.cyan(),
                     plain_long = PLAIN_LONG.cyan(),
+                    trace_format_long = TRACE_FORMAT_LONG.cyan(),
+                    trace_format_value =
+                        format!("[{TEXT_TRACE_FORMAT} | {PLAIN_TRACE_FORMAT} | {JSON_TRACE_FORMAT}]")
+                            .cyan(),
                     no_output_long = NO_OUTPUT_LONG.cyan(),
+                    format_short = FORMAT_SHORT.cyan(),
+                    format_long = FORMAT_LONG.cyan(),
+                    format_value = format!("[{}]", format_names().join(" | ")).cyan(),
+                    journal_long = JOURNAL_LONG.cyan(),
+                    backups_long = BACKUPS_LONG.cyan(),
+                    backups_value = "<n>".cyan(),
+                    save_interval_long = SAVE_INTERVAL_LONG.cyan(),
+                    save_interval_value = "<seconds>".cyan(),
+                    default_save_interval = DEFAULT_SAVE_INTERVAL.as_secs(),
+                    save_every_long = SAVE_EVERY_LONG.cyan(),
+                    save_every_value = "<n>".cyan(),
+                    sqlite_note = SQLITE_NOTE,
+                    sort_long = SORT_LONG.cyan(),
+                    sort_value = format!("[{KEY_ORDER} | {COUNT_ORDER}]").cyan(),
+                    trace_sqlite_note = TRACE_SQLITE_NOTE,
                 );
 
                 std::process::exit(0);
@@ -475,9 +689,9 @@ fn main() {
         }
     }
 
-    if trace_plain_style && trace_path.is_none() {
+    if trace_format.is_some() && trace_path.is_none() {
         println!(
-            "{warning} {PLAIN_SHORT} {or} {PLAIN_LONG} {text}{TRACE_SHORT} {pipe} {TRACE_LONG}{brace}",
+            "{warning} {PLAIN_SHORT}, {PLAIN_LONG} {or} {TRACE_FORMAT_LONG} {text}{TRACE_SHORT} {pipe} {TRACE_LONG}{brace}",
             warning = "warning!:".yellow(),
             or = "or".yellow(),
             text = "ignored becouse you do not specified trace option (".yellow(),
@@ -497,6 +711,47 @@ fn main() {
         );
     }
 
+    if !collect.is_empty() && (ngram != 1 || no_chords) {
+        println!(
+            "{warning} {PAIRS_SHORT}, {PAIRS_LONG}, {NGRAM_LONG} {or} {NO_CHORDS_LONG} {text}{COLLECT_SHORT} {pipe} {COLLECT_LONG}{brace}",
+            warning = "warning!:".yellow(),
+            or = "or".yellow(),
+            text = "ignored becouse you do specified collect option (".yellow(),
+            pipe = "|".yellow(),
+            brace = ")".yellow(),
+        );
+    }
+
+    let mut options_config = if collect.is_empty() {
+        Config::new(ngram, no_chords, dwell, flight, VERSION.into())
+    } else {
+        Config::collecting(collect, dwell, flight, VERSION.into())
+    };
+
+    if flight && !options_config.modes().iter().any(|mode| mode.ngram == 2) {
+        println!(
+            "{warning} {FLIGHT_LONG} {text}{PAIRS_SHORT} {pipe} {PAIRS_LONG}{or} {CHORD_PAIRS_SECTION} {pipe} {KEY_PAIRS_SECTION} {of} {COLLECT_LONG}{brace}",
+            warning = "warning!:".yellow(),
+            text = "ignored becouse no pairs are counted (".yellow(),
+            pipe = "|".yellow(),
+            or = ", or".yellow(),
+            of = "section of".yellow(),
+            brace = ")".yellow(),
+        );
+        options_config.flight = false;
+    }
+
+    if !devices.is_empty() && backend != Backend::Evdev {
+        println!(
+            "{warning} {DEVICE_SHORT} {or} {DEVICE_LONG} {text}{BACKEND_SHORT} {pipe} {BACKEND_LONG} {EVDEV_BACKEND_KEY}{brace}",
+            warning = "warning!:".yellow(),
+            or = "or".yellow(),
+            text = "ignored becouse you do not specified evdev backend (".yellow(),
+            pipe = "|".yellow(),
+            brace = ")".yellow(),
+        );
+    }
+
     if force_modify_output && no_output {
         println!(
             "{warning} {MODIFY_OUTPUT_SHORT} {or} {MODIFY_OUTPUT_LONG} {text}{NO_OUTPUT_LONG}{brace}",
@@ -518,319 +773,1582 @@ fn main() {
     }
 
     // process the output file
+    if !no_output && statistic_path.is_none() {
+        let default_path = match format {
+            Some(Format::Json) => DEFAULT_STATISTIC_PATH_JSON,
+            _ => DEFAULT_STATISTIC_PATH_YAML,
+        };
+        statistic_path = Some(Path::new(default_path).to_path_buf());
+    }
+    let format = format
+        .or_else(|| statistic_path.as_deref().and_then(Format::from_path))
+        .unwrap_or_default();
+
     if !no_output {
-        if statistic_path == None {
-            statistic_path = Some(Path::new(DEFAULT_STATISTIC_PATH_YAML).to_path_buf());
-        }
         let path = statistic_path.as_ref().unwrap();
 
         if path.exists() {
-            let mut file = File::open(&path)
-                .or_else(|_| -> Result<_, ()> {
-                    println!(
-                        "{} {} {}",
-                        "file in".red(),
-                        path.to_string(),
-                        "exists but cannot be open".red()
-                    );
-                    std::process::exit(1);
-                })
-                .unwrap();
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)
-                .or_else(|_| -> Result<_, ()> {
-                    println!(
-                        "{} {} {}",
-                        "file in".red(),
-                        path.to_string(),
-                        "exists but cannot be read".red()
-                    );
-                    std::process::exit(1);
-                })
-                .unwrap();
-
-            key_counts = serde_yaml::from_str(&contents)
-                .or_else(|_| -> Result<_, ()> {
-                    println!(
-                        "{} {} {}",
-                        "error: data in output file".red(),
-                        path.to_string(),
-                        "not valid and cannot be deserialize".red()
-                    );
-                    std::process::exit(1);
-                })
-                .unwrap();
+            key_counts = KeyCounts::load_as(path, format).unwrap_or_else(|error| exit_with(error));
 
-            ask_modify(force_modify_output, path);
+            ask_modify(force_modify_output, path).unwrap_or_else(|error| exit_with(error));
         }
     }
 
+    let modes = options_config.modes();
+    let flight = options_config.flight;
     if let Some(ref mut config) = key_counts.config {
-        check_config(
-            config,
-            force_modify_output,
-            pairs,
-            no_chords,
-            statistic_path.as_ref().unwrap(),
-        );
+        let path = statistic_path.as_ref().unwrap();
+        if let Err(error) = config.check(&options_config) {
+            println!(
+                "{}",
+                format!(
+                    "Config in output file that you provide {:?} do not match to your options\nit is means different settings were used to create this file",
+                    path
+                )
+                .red()
+            );
+            exit_with(error);
+        }
+        check_version(config, force_modify_output).unwrap_or_else(|error| exit_with(error));
     } else {
-        key_counts.config = Some(Config::new(pairs, no_chords, VERSION.into()));
+        key_counts.set_config(options_config);
     }
 
+    let statistic_path = if no_output { None } else { statistic_path };
+
     // save first time to check open/write errors
-    save_data(&key_counts, statistic_path.as_ref().unwrap(), no_output);
+    let mut capture_journal = None;
+    if let Some(ref path) = statistic_path {
+        // counts of a capture that was killed before saving them
+        let journal_path = journal::journal_path(path);
+        let recovered = journal::replay(&journal_path, &mut key_counts)
+            .unwrap_or_else(|error| exit_with(error));
+        if recovered {
+            println!(
+                "{} {:?}",
+                "warning!: recovered unsaved counts from".yellow(),
+                journal_path
+            );
+        }
+
+        persist::rotate_backups(path, backups)
+            .map_err(|error| Error::io(path, error))
+            .unwrap_or_else(|error| exit_with(error));
+        key_counts
+            .save_as(path, format)
+            .unwrap_or_else(|error| exit_with(error));
+
+        if use_journal {
+            capture_journal = Some(
+                Journal::create(&journal_path, key_counts.journal + 1)
+                    .unwrap_or_else(|error| exit_with(error)),
+            );
+        } else if journal_path.exists() {
+            fs::remove_file(&journal_path)
+                .map_err(|error| Error::io(&journal_path, error))
+                .unwrap_or_else(|error| exit_with(error));
+        }
+    }
+
+    let trace_format = trace_format
+        .or_else(|| trace_path.as_deref().and_then(TraceFormat::from_path))
+        .unwrap_or_default();
+
+    // a database keeps every session, so it is never asked to be modified
+    #[cfg(feature = "sqlite")]
+    let trace_in_database =
+        trace_path.as_deref().and_then(Format::from_path) == Some(Format::Sqlite);
+    #[cfg(not(feature = "sqlite"))]
+    let trace_in_database = false;
+
     if let Some(ref trace_path) = trace_path {
-        if trace_path.exists() {
-            ask_modify(force_modify_trace, trace_path);
+        if !trace_in_database {
+            if trace_path.exists() {
+                ask_modify(force_modify_trace, trace_path).unwrap_or_else(|error| exit_with(error));
+            }
+            upend_trace(TraceStep::Empty, trace_path, false)
+                .unwrap_or_else(|error| exit_with(error));
         }
-        upend_trace(TraceStep::Empty, &trace_path, trace_plain_style);
     }
 
-    let device_state = DeviceState::new();
-    let mut last_keys = Vec::new();
-    let mut last_pair = Vec::new();
+    // the session goes with the trace, or with the statistic if only they are in a database
+    #[cfg(feature = "sqlite")]
+    let mut session = {
+        let session_path = if trace_in_database {
+            trace_path.as_ref()
+        } else {
+            statistic_path.as_ref().filter(|_| format == Format::Sqlite)
+        };
+        session_path.map(|path| {
+            Database::open(path)
+                .and_then(|mut database| {
+                    let session = database.start_session(VERSION)?;
+                    Ok((database, session))
+                })
+                .unwrap_or_else(|error| exit_with(error))
+        })
+    };
+
+    let mut source = open_source(backend, sensitivity, &devices);
+    let start = Instant::now();
+    key_counts.order = order;
+    let mut capture = Capture::new(key_counts, modes);
+    capture.statistic_path = statistic_path;
+    capture.format = format;
+    capture.trace_path = trace_path;
+    capture.trace_format = trace_format;
+    capture.dwell = dwell;
+    capture.flight = flight;
+    capture.save_interval = save_interval;
+    capture.save_every = save_every;
+    capture.journal = capture_journal;
+    #[cfg(feature = "sqlite")]
+    if trace_in_database {
+        capture.trace_session = session.take();
+    }
 
     let mut stdout = stdout();
 
     if verbose {
+        VERBOSE.store(true, Ordering::Relaxed);
+        log::set_max_level(log::max_level().max(log::LevelFilter::Info));
         enable_raw_mode().expect("enable_raw_mode problem");
         execute!(stdout, EnterAlternateScreen).expect("EnterAlternateScreen problem");
     }
 
-    let start = Instant::now();
-    let mut last_duration = start.elapsed();
-    let mut some = false;
-
-    loop {
-        let keys = device_state.get_keys();
-
-        // Check for new key presses
-        for key in &keys {
-            if !last_keys.contains(key) {
-                some = true;
-                if no_chords {
-                    if pairs {
-                        // skip first iteration becouse it is have not pair
-                        if last_pair.len() != 0 {
-                            let count_item = CountItem::Pair(last_pair, vec![*key]);
-                            *key_counts.entry(count_item.clone()).or_insert(0) += 1;
-
-                            verbose!(
-                                verbose,
-                                "{:?} has been pressed {} times",
-                                count_item,
-                                key_counts[&count_item]
-                            );
-
-                            // Is this expensive?)
-                            save_data(&key_counts, statistic_path.as_ref().unwrap(), no_output);
-                        }
-
-                        last_pair = vec![*key];
-                    } else {
-                        let count_item = CountItem::Single(vec![*key]);
-                        *key_counts.entry(count_item.clone()).or_insert(0) += 1;
-                        verbose!(
-                            verbose,
-                            "{:?} has been pressed {} times",
-                            *key,
-                            key_counts[&count_item]
-                        );
-
-                        // Is this expensive?)
-                        save_data(&key_counts, statistic_path.as_ref().unwrap(), no_output);
-                    }
-
-                    if let Some(ref trace_path) = trace_path {
-                        let duration = start.elapsed();
-                        let step = if first_trace_step {
-                            first_trace_step = false;
-                            TraceStep::First(vec![*key])
-                        } else {
-                            TraceStep::Regular(vec![*key], duration - last_duration)
-                        };
+    let stop = register_stop();
+    let result = run(&mut *source, &mut capture, &stop).and_then(|()| capture.flush());
+    #[cfg(feature = "sqlite")]
+    let result = result.and_then(|()| {
+        let inputs = capture.inputs();
+        match session.as_mut().or(capture.trace_session.as_mut()) {
+            Some((database, session)) => database.end_session(*session, inputs),
+            None => Ok(()),
+        }
+    });
 
-                        last_duration = duration;
+    if verbose {
+        execute!(stdout, LeaveAlternateScreen).expect("LeaveAlternateScreen problem");
+        disable_raw_mode().expect("disable_raw_mode problem");
+    }
 
-                        upend_trace(step, &trace_path, trace_plain_style);
-                    }
-                }
-            }
-        }
+    if let Err(error) = result {
+        exit_with(error);
+    }
 
-        if some {
-            if !no_chords {
-                if pairs {
-                    // skip first iteration becouse it is have not pair
-                    if last_pair.len() != 0 {
-                        let count_item = CountItem::Pair(last_pair, keys.clone());
-                        *key_counts.entry(count_item.clone()).or_insert(0) += 1;
-                        verbose!(
-                            verbose,
-                            "{:?} has been pressed {} times",
-                            count_item,
-                            key_counts[&count_item]
-                        );
+    print_summary(&capture, start.elapsed());
+}
 
-                        // Is this expensive?)
-                        save_data(&key_counts, statistic_path.as_ref().unwrap(), no_output);
-                    }
+/// Flag raised by SIGINT, SIGTERM or SIGHUP, so capture can stop and save.
+///
+/// A second signal terminates at once, in case stopping hangs.
+fn register_stop() -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+
+    #[cfg(unix)]
+    let signals = [SIGINT, SIGTERM, SIGHUP];
+    #[cfg(not(unix))]
+    let signals = [SIGINT, SIGTERM];
+
+    for signal in signals {
+        flag::register_conditional_shutdown(signal, EXIT_SIGNAL, Arc::clone(&stop))
+            .and_then(|_| flag::register(signal, Arc::clone(&stop)))
+            .map_err(|error| {
+                println!("{} {}", "cannot handle signal:".red(), error);
+                std::process::exit(1);
+            })
+            .unwrap();
+    }
 
-                    last_pair = keys.clone();
-                } else {
-                    let count_item = CountItem::Single(keys.clone());
-                    *key_counts.entry(count_item.clone()).or_insert(0) += 1;
-                    verbose!(
-                        verbose,
-                        "{:?} has been pressed {} times",
-                        keys.clone(),
-                        key_counts[&count_item]
-                    );
+    stop
+}
 
-                    // Is this expensive?)
-                    save_data(&key_counts, statistic_path.as_ref().unwrap(), no_output);
-                }
+fn print_summary(capture: &Capture, duration: Duration) {
+    println!(
+        "\r{} {} {} {:.0?}",
+        "captured".green(),
+        capture.inputs(),
+        "inputs in".green(),
+        duration
+    );
+    if let Some(ref path) = capture.statistic_path {
+        println!("{} {:?}", "statistic saved to".green(), path);
+    }
+    if let Some(ref path) = capture.trace_path {
+        println!("{} {:?}", "trace saved to".green(), path);
+    }
+}
 
-                if let Some(ref trace_path) = trace_path {
-                    let duration = start.elapsed();
-                    let step = if first_trace_step {
-                        first_trace_step = false;
-                        TraceStep::First(keys.clone())
-                    } else {
-                        TraceStep::Regular(keys.clone(), duration - last_duration)
-                    };
+/// Feed every event of `source` through `capture`
+/// until the source is exhausted, `stop` is raised or the user asks to stop.
+fn run(source: &mut dyn KeySource, capture: &mut Capture, stop: &AtomicBool) -> Result<()> {
+    while let Some(events) = source.poll() {
+        capture.update(events)?;
 
-                    last_duration = duration;
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
 
-                    upend_trace(step, &trace_path, trace_plain_style);
+        if VERBOSE.load(Ordering::Relaxed) && poll(Duration::ZERO).expect("poll error") {
+            if let Event::Key(event) = read().expect("read error") {
+                if event.code == KeyCode::Char('c')
+                    && event.modifiers.contains(KeyModifiers::CONTROL)
+                {
+                    break;
                 }
+                // Handle other key events here
             }
-            some = false;
         }
+    }
 
-        last_keys = keys;
+    Ok(())
+}
 
-        if verbose && poll(Duration::from_millis(sensitivity)).expect("poll error") {
-            match read().expect("read error") {
-                Event::Key(event) => {
-                    if event.code == KeyCode::Char('c')
-                        && event.modifiers.contains(KeyModifiers::CONTROL)
-                    {
-                        break;
-                    }
-                    // Handle other key events here
-                }
-                _ => {}
-            }
-        } else {
-            thread::sleep(Duration::from_millis(sensitivity));
+fn open_source(backend: Backend, sensitivity: u64, devices: &[PathBuf]) -> Box<dyn KeySource> {
+    match backend {
+        Backend::DeviceQuery => {
+            Box::new(DeviceQuerySource::new(Duration::from_millis(sensitivity)))
+        }
+        #[cfg(target_os = "linux")]
+        Backend::Evdev => Box::new(
+            hectic_key_capture::evdev::EvdevSource::open(devices)
+                .map_err(|error| {
+                    println!("{} {}", "cannot open event devices:".red(), error);
+                    std::process::exit(1);
+                })
+                .unwrap(),
+        ),
+        #[cfg(not(target_os = "linux"))]
+        Backend::Evdev => {
+            let _ = devices;
+            println!(
+                "{}",
+                "error: evdev backend is available only on Linux".red()
+            );
+            std::process::exit(1);
         }
     }
+}
 
-    if verbose {
-        execute!(stdout, LeaveAlternateScreen).expect("LeaveAlternateScreen problem");
-        disable_raw_mode().expect("disable_raw_mode problem");
-    }
+/// Value of option `arg`, which is named `name` in the error message.
+fn option_value(args: &mut impl Iterator<Item = String>, arg: &str, name: &str) -> String {
+    args.next()
+        .or_else(|| {
+            println!(
+                "{} {arg} {}",
+                "error: necessary value for option".red(),
+                format!("({name})").red()
+            );
+            std::process::exit(1);
+        })
+        .unwrap()
 }
 
-fn save_data(data: &KeyCounts, path: &PathBuf, no_output: bool) {
-    if no_output {
-        return;
-    }
-    let serialized = serde_yaml::to_string(data).expect("serialize to yaml panic");
-    let mut file = File::create(path)
-        .or_else(|_| -> Result<_, ()> {
-            println!("{} {}", "cannot create / open file".red(), path.to_string(),);
+/// Sections of a comma separated `--collect` value.
+fn parse_collect(value: &str) -> Vec<Mode> {
+    value
+        .split(',')
+        .map(|section| {
+            section
+                .trim()
+                .parse::<Mode>()
+                .map_err(|_| {
+                    println!(
+                        "{} {section} {}\n{} {CHORDS_SECTION}, {KEYS_SECTION}, {CHORD_PAIRS_SECTION}, {KEY_PAIRS_SECTION}, chord-<n>grams {or} key-<n>grams",
+                        "error:".red(),
+                        "is not valid section to collect".red(),
+                        "must be one of".red(),
+                        or = "or".red(),
+                    );
+                    std::process::exit(1);
+                })
+                .unwrap()
+        })
+        .collect()
+}
+
+fn parse_section(value: &str) -> Mode {
+    value
+        .parse::<Mode>()
+        .map_err(|_| {
+            println!(
+                "{} {value} {}",
+                "error:".red(),
+                "is not valid section".red(),
+            );
             std::process::exit(1);
         })
-        .unwrap();
-    file.write_all(serialized.as_bytes())
-        .or_else(|_| -> Result<_, ()> {
-            println!("{} {}", "cannot write to file".red(), path.to_string(),);
+        .unwrap()
+}
+
+fn parse_order(value: &str) -> Order {
+    value
+        .parse::<Order>()
+        .map_err(|_| {
+            println!(
+                "{} {value} {}\n{} {KEY_ORDER} {or} {COUNT_ORDER}",
+                "error:".red(),
+                "is not valid value for sort".red(),
+                "must be".red(),
+                or = "or".red(),
+            );
             std::process::exit(1);
         })
-        .unwrap();
+        .unwrap()
 }
 
-fn upend_trace(trace_step: TraceStep, path: &PathBuf, trace_plain_style: bool) {
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(path)
-        .or_else(|_| -> Result<_, ()> {
-            println!("{} {}", "cannot create / open file".red(), path.to_string(),);
+fn parse_layout(value: &str) -> Layout {
+    value
+        .parse::<Layout>()
+        .map_err(|_| {
+            println!(
+                "{} {value} {}\n{} {ANSI_LAYOUT}, {ISO_LAYOUT}, {ORTHO_LAYOUT} {or} {SPLIT_LAYOUT}",
+                "error:".red(),
+                "is not valid value for layout".red(),
+                "must be".red(),
+                or = "or".red(),
+            );
             std::process::exit(1);
         })
-        .unwrap();
+        .unwrap()
+}
 
-    let text = match trace_step {
-        TraceStep::First(keycode) => input_to_string(keycode),
-        TraceStep::Regular(keycode, duration) => {
-            if trace_plain_style {
-                input_to_string(keycode)
-            } else {
-                format!("{} after {:?}", input_to_string(keycode), duration).replace("\"", "")
-            }
-        }
-        TraceStep::Empty => return,
-    };
+fn parse_top(value: &str) -> usize {
+    value
+        .parse::<usize>()
+        .map_err(|_| {
+            println!(
+                "{} {value} {}\n{}",
+                "error:".red(),
+                "is not valid value for top".red(),
+                "must be a number".red(),
+            );
+            std::process::exit(1);
+        })
+        .unwrap()
+}
 
-    if trace_plain_style {
-        write!(file, "{} ", text)
-            .or_else(|_| -> Result<_, ()> {
-                println!("{} {}", "cannot write to file ".red(), path.to_string(),);
-                std::process::exit(1);
-            })
-            .unwrap();
-    } else {
-        write!(file, "{}\n", text)
-            .or_else(|_| -> Result<_, ()> {
-                println!("{} {}", "cannot write to file ".red(), path.to_string(),);
+fn parse_ngram(value: &str) -> usize {
+    match value.parse::<usize>() {
+        Ok(ngram) if ngram > 0 => ngram,
+        _ => {
+            println!(
+                "{} {value} {}\n{}",
+                "error:".red(),
+                "is not valid value for ngram".red(),
+                "must be a number > 0".red(),
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_trace_format(value: &str) -> TraceFormat {
+    value
+        .parse::<TraceFormat>()
+        .map_err(|_| {
+            println!(
+                "{} {value} {}\n{} {TEXT_TRACE_FORMAT}, {PLAIN_TRACE_FORMAT} {or} {JSON_TRACE_FORMAT}",
+                "error:".red(),
+                "is not valid value for trace format".red(),
+                "must be".red(),
+                or = "or".red(),
+            );
+            std::process::exit(1);
+        })
+        .unwrap()
+}
+
+/// Names of formats this build can read and write.
+fn format_names() -> Vec<&'static str> {
+    vec![
+        YAML_FORMAT,
+        JSON_FORMAT,
+        BINARY_FORMAT,
+        #[cfg(feature = "sqlite")]
+        SQLITE_FORMAT,
+    ]
+}
+
+fn parse_format(value: &str) -> Format {
+    value
+        .parse::<Format>()
+        .map_err(|_| {
+            println!(
+                "{} {value} {}\n{} {}",
+                "error:".red(),
+                "is not valid value for format".red(),
+                "must be one of".red(),
+                format_names().join(", "),
+            );
+            std::process::exit(1);
+        })
+        .unwrap()
+}
+
+/// `convert` command: rewrite a statistic file in another format.
+fn convert(program_name: &str, mut args: impl Iterator<Item = String>) {
+    let mut from = None;
+    let mut to = None;
+    let mut order = Order::default();
+    let mut paths = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            FROM_LONG => from = Some(parse_format(&option_value(&mut args, &arg, "from"))),
+            FORMAT_SHORT | FORMAT_LONG => {
+                to = Some(parse_format(&option_value(&mut args, &arg, "format")))
+            }
+            SORT_LONG => order = parse_order(&option_value(&mut args, &arg, "sort")),
+            HELP_SHORT | HELP_LONG => {
+                println!(
+                    r#"Convert statistic file to another format, nothing is lost on the way
+
+{usage_title} {usage_content}
+
+{optiongs_title}
+    {from_long} {format_value}
+                    Format of input file
+
+                    {default} by extension of input file, {YAML_FORMAT} if unknown
+
+    {format_short}, {format_long} {format_value}
+                    Format of output file
+
+                    {default} by extension of output file, {YAML_FORMAT} if unknown
+
+    {sort_long} {sort_value}
+                    Order counts are written in, see capture {help_long}
+
+                    {default} {KEY_ORDER}
+
+    {help_short}, {help_long}
+                    This message"#,
+                    usage_title = "Usage:".green(),
+                    optiongs_title = "Options:".green(),
+                    usage_content =
+                        format!("{program_name} {CONVERT_COMMAND} [OPTIONS] <input> <output>")
+                            .cyan(),
+                    default = "Default:".green(),
+                    from_long = FROM_LONG.cyan(),
+                    format_short = FORMAT_SHORT.cyan(),
+                    format_long = FORMAT_LONG.cyan(),
+                    format_value = format!("[{}]", format_names().join(" | ")).cyan(),
+                    sort_long = SORT_LONG.cyan(),
+                    sort_value = format!("[{KEY_ORDER} | {COUNT_ORDER}]").cyan(),
+                    help_short = HELP_SHORT.cyan(),
+                    help_long = HELP_LONG.cyan(),
+                );
+                std::process::exit(0);
+            }
+            _ if arg.starts_with('-') => {
+                println!("Unhandled option: {}", arg);
+                std::process::exit(1);
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [input, output] = paths.as_slice() else {
+        println!(
+            "{} {}",
+            "error: expected input and output files, see".red(),
+            format!("{program_name} {CONVERT_COMMAND} {HELP_LONG}").cyan()
+        );
+        std::process::exit(1);
+    };
+
+    let from = from
+        .or_else(|| Format::from_path(input))
+        .unwrap_or_default();
+    let to = to.or_else(|| Format::from_path(output)).unwrap_or_default();
+
+    KeyCounts::load_as(input, from)
+        .and_then(|mut key_counts| {
+            key_counts.order = order;
+            key_counts.save_as(output, to)
+        })
+        .unwrap_or_else(|error| exit_with(error));
+}
+
+/// `export` command: write counts of one section as a table.
+fn export(program_name: &str, mut args: impl Iterator<Item = String>) {
+    let mut from = None;
+    let mut section: Option<Mode> = None;
+    let mut tsv = false;
+    let mut output: Option<PathBuf> = None;
+    let mut input: Option<PathBuf> = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            FROM_LONG => from = Some(parse_format(&option_value(&mut args, &arg, "from"))),
+            SECTION_LONG => {
+                section = Some(parse_section(&option_value(&mut args, &arg, "section")))
+            }
+            TSV_LONG => tsv = true,
+            OUTPUT_SHORT | OUTPUT_LONG => {
+                output = Some(PathBuf::from(option_value(&mut args, &arg, "output")))
+            }
+            HELP_SHORT | HELP_LONG => {
+                println!(
+                    r#"Write counts as CSV or TSV table, one row per counted item
+with a column for every key of it and the count
+
+{usage_title} {usage_content}
+
+{optiongs_title}
+    {section_long} {section_value}
+                    Section to write, as in {collect_short}, {collect_long}
+
+                    {default} the only section of input file
+
+    {tsv_long}
+                    Separate columns with tabs
+
+                    {default} commas, unless output file ends with .tsv
+
+    {output_short}, {output_long} {output_value}
+                    Output file
+
+                    {default} standard output
+
+    {from_long} {format_value}
+                    Format of input file
+
+                    {default} by extension of input file, {YAML_FORMAT} if unknown
+
+    {help_short}, {help_long}
+                    This message"#,
+                    usage_title = "Usage:".green(),
+                    optiongs_title = "Options:".green(),
+                    usage_content =
+                        format!("{program_name} {EXPORT_COMMAND} [OPTIONS] <input>").cyan(),
+                    default = "Default:".green(),
+                    section_long = SECTION_LONG.cyan(),
+                    section_value = "<section>".cyan(),
+                    collect_short = COLLECT_SHORT.cyan(),
+                    collect_long = COLLECT_LONG.cyan(),
+                    tsv_long = TSV_LONG.cyan(),
+                    output_short = OUTPUT_SHORT.cyan(),
+                    output_long = OUTPUT_LONG.cyan(),
+                    output_value = "<path>".cyan(),
+                    from_long = FROM_LONG.cyan(),
+                    format_value = format!("[{}]", format_names().join(" | ")).cyan(),
+                    help_short = HELP_SHORT.cyan(),
+                    help_long = HELP_LONG.cyan(),
+                );
+                std::process::exit(0);
+            }
+            _ if arg.starts_with('-') || input.is_some() => {
+                println!("Unhandled option: {}", arg);
                 std::process::exit(1);
+            }
+            _ => input = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let Some(input) = input else {
+        println!(
+            "{} {}",
+            "error: expected input file, see".red(),
+            format!("{program_name} {EXPORT_COMMAND} {HELP_LONG}").cyan()
+        );
+        std::process::exit(1);
+    };
+
+    let from = from
+        .or_else(|| Format::from_path(&input))
+        .unwrap_or_default();
+    let key_counts = KeyCounts::load_as(&input, from).unwrap_or_else(|error| exit_with(error));
+
+    let modes = key_counts.config.clone().unwrap_or_default().modes();
+    let mode = match section {
+        Some(mode) => mode,
+        None if modes.len() == 1 => modes[0],
+        None => {
+            let sections = modes
+                .iter()
+                .map(Mode::to_string)
+                .collect::<Vec<String>>()
+                .join(", ");
+            println!(
+                "{} {SECTION_LONG} {}{}",
+                "error: input file has several sections, choose one with".red(),
+                "of ".red(),
+                sections.cyan()
+            );
+            std::process::exit(1);
+        }
+    };
+    let Some(counts) = key_counts.counts(&mode) else {
+        println!("{} {} {:?}", "error: no section".red(), mode, input);
+        std::process::exit(1);
+    };
+
+    let tsv = tsv
+        || output
+            .as_ref()
+            .and_then(|path| path.extension())
+            .map_or(false, |extension| extension.eq_ignore_ascii_case("tsv"));
+    let delimiter = if tsv { TSV_DELIMITER } else { CSV_DELIMITER };
+
+    let result = match output {
+        Some(ref path) => File::create(path)
+            .map(io::BufWriter::new)
+            .and_then(|mut file| {
+                export::write_table(&mut file, counts, mode.ngram, delimiter)?;
+                file.flush()
             })
-            .unwrap();
+            .map_err(|error| Error::io(path, error)),
+        None => export::write_table(&mut io::stdout().lock(), counts, mode.ngram, delimiter)
+            .map_err(|source| Error::Io { path: None, source }),
+    };
+    result.unwrap_or_else(|error| exit_with(error));
+}
+
+/// `replay` command: count a trace file again under any sections.
+fn replay(program_name: &str, mut args: impl Iterator<Item = String>) {
+    let mut ngram = 1;
+    let mut no_chords = false;
+    let mut collect: Vec<Mode> = Vec::new();
+    let mut dwell = false;
+    let mut flight = false;
+    let mut trace_format: Option<TraceFormat> = None;
+    let mut format = None;
+    let mut order = Order::default();
+    let mut paths = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            PAIRS_SHORT | PAIRS_LONG => ngram = 2,
+            NGRAM_LONG => ngram = parse_ngram(&option_value(&mut args, &arg, "ngram")),
+            COLLECT_SHORT | COLLECT_LONG => {
+                collect.extend(parse_collect(&option_value(&mut args, &arg, "collect")))
+            }
+            NO_CHORDS_LONG => no_chords = true,
+            DWELL_LONG => dwell = true,
+            FLIGHT_LONG => flight = true,
+            PLAIN_SHORT | PLAIN_LONG => trace_format = Some(TraceFormat::Plain),
+            TRACE_FORMAT_LONG => {
+                trace_format = Some(parse_trace_format(&option_value(
+                    &mut args,
+                    &arg,
+                    "trace format",
+                )))
+            }
+            FORMAT_SHORT | FORMAT_LONG => {
+                format = Some(parse_format(&option_value(&mut args, &arg, "format")))
+            }
+            SORT_LONG => order = parse_order(&option_value(&mut args, &arg, "sort")),
+            HELP_SHORT | HELP_LONG => {
+                println!(
+                    r#"Count a trace file again, as if it was typed under other options
+
+Text and plain traces do not keep releases, so {dwell_long} needs a {JSON_TRACE_FORMAT} trace;
+plain traces do not keep times either, so {flight_long} needs a {TEXT_TRACE_FORMAT} or {JSON_TRACE_FORMAT} one
+
+{usage_title} {usage_content}
+
+{optiongs_title}
+    {pairs_short}, {pairs_long}
+                    Count buttons pairs, same as {ngram_long} 2
+
+    {ngram_long} {ngram_value}
+                    Count n inputs pressed one after another
+
+                    {default} 1
+
+    {collect_short}, {collect_long} {collect_value}
+                    Count several sections at once, see capture {help_long}
+
+    {no_chords_long}
+                    Count keys separately not paying attention to simultaneous presses
+
+    {dwell_long}
+                    Also save how long each key is held down
+
+    {flight_long}
+                    When pairs are counted, by {pairs_short}, {pairs_long} or a
+                    {CHORD_PAIRS_SECTION} or {KEY_PAIRS_SECTION} section of {collect_long},
+                    also save time between the inputs of each pair
+
+    {plain_short}, {plain_long}
+                    Same as {trace_format_long} {PLAIN_TRACE_FORMAT}
+
+    {trace_format_long} {trace_format_value}
+                    Format of trace file
+
+                    {default} by extension of trace file ({JSON_TRACE_FORMAT} for .jsonl, .ndjson),
+                    {TEXT_TRACE_FORMAT} if unknown
+
+    {format_short}, {format_long} {format_value}
+                    Format of output file
+
+                    {default} by extension of output file, {YAML_FORMAT} if unknown
+
+    {sort_long} {sort_value}
+                    Order counts are written in, see capture {help_long}
+
+                    {default} {KEY_ORDER}
+
+    {help_short}, {help_long}
+                    This message"#,
+                    usage_title = "Usage:".green(),
+                    optiongs_title = "Options:".green(),
+                    usage_content =
+                        format!("{program_name} {REPLAY_COMMAND} [OPTIONS] <trace> <output>")
+                            .cyan(),
+                    default = "Default:".green(),
+                    pairs_short = PAIRS_SHORT.cyan(),
+                    pairs_long = PAIRS_LONG.cyan(),
+                    ngram_long = NGRAM_LONG.cyan(),
+                    ngram_value = "<n>".cyan(),
+                    collect_short = COLLECT_SHORT.cyan(),
+                    collect_long = COLLECT_LONG.cyan(),
+                    collect_value = "<section>[,<section>...]".cyan(),
+                    no_chords_long = NO_CHORDS_LONG.cyan(),
+                    dwell_long = DWELL_LONG.cyan(),
+                    flight_long = FLIGHT_LONG.cyan(),
+                    plain_short = PLAIN_SHORT.cyan(),
+                    plain_long = PLAIN_LONG.cyan(),
+                    trace_format_long = TRACE_FORMAT_LONG.cyan(),
+                    trace_format_value = format!(
+                        "[{TEXT_TRACE_FORMAT} | {PLAIN_TRACE_FORMAT} | {JSON_TRACE_FORMAT}]"
+                    )
+                    .cyan(),
+                    format_short = FORMAT_SHORT.cyan(),
+                    format_long = FORMAT_LONG.cyan(),
+                    format_value = format!("[{}]", format_names().join(" | ")).cyan(),
+                    sort_long = SORT_LONG.cyan(),
+                    sort_value = format!("[{KEY_ORDER} | {COUNT_ORDER}]").cyan(),
+                    help_short = HELP_SHORT.cyan(),
+                    help_long = HELP_LONG.cyan(),
+                );
+                std::process::exit(0);
+            }
+            _ if arg.starts_with('-') => {
+                println!("Unhandled option: {}", arg);
+                std::process::exit(1);
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [trace, output] = paths.as_slice() else {
+        println!(
+            "{} {}",
+            "error: expected trace and output files, see".red(),
+            format!("{program_name} {REPLAY_COMMAND} {HELP_LONG}").cyan()
+        );
+        std::process::exit(1);
+    };
+
+    let trace_format = trace_format
+        .or_else(|| TraceFormat::from_path(trace))
+        .unwrap_or_default();
+    let format = format
+        .or_else(|| Format::from_path(output))
+        .unwrap_or_default();
+
+    if dwell && trace_format != TraceFormat::Json {
+        println!(
+            "{} {DWELL_LONG} {} {JSON_TRACE_FORMAT} {}",
+            "error:".red(),
+            "needs a".red(),
+            "trace, this one does not keep releases".red(),
+        );
+        std::process::exit(1);
+    }
+    if flight && trace_format == TraceFormat::Plain {
+        println!(
+            "{} {FLIGHT_LONG} {} {PLAIN_TRACE_FORMAT} {}",
+            "error:".red(),
+            "can not be counted from".red(),
+            "trace, it does not keep times".red(),
+        );
+        std::process::exit(1);
     }
+
+    let config = if collect.is_empty() {
+        Config::new(ngram, no_chords, dwell, flight, VERSION.into())
+    } else {
+        Config::collecting(collect, dwell, flight, VERSION.into())
+    };
+    let modes = config.modes();
+    let mut key_counts = KeyCounts::new();
+    key_counts.set_config(config);
+    key_counts.order = order;
+
+    let mut capture = Capture::new(key_counts, modes);
+    capture.dwell = dwell;
+    capture.flight = flight;
+
+    replay::replay(trace, trace_format, &mut capture)
+        .and_then(|_| capture.key_counts.save_as(output, format))
+        .unwrap_or_else(|error| exit_with(error));
+
+    println!(
+        "{} {} {} {:?}",
+        "replayed".green(),
+        capture.inputs(),
+        "inputs to".green(),
+        output
+    );
 }
 
-fn check_config(
-    config: &mut Config,
-    force_modify_output: bool,
-    pairs: bool,
-    no_chords: bool,
-    path: &PathBuf,
+/// `merge` command: sum several statistic files into one.
+fn merge(program_name: &str, mut args: impl Iterator<Item = String>) {
+    let mut from = None;
+    let mut format = None;
+    let mut convert = false;
+    let mut order = Order::default();
+    let mut output: Option<PathBuf> = None;
+    let mut inputs: Vec<PathBuf> = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            FROM_LONG => from = Some(parse_format(&option_value(&mut args, &arg, "from"))),
+            FORMAT_SHORT | FORMAT_LONG => {
+                format = Some(parse_format(&option_value(&mut args, &arg, "format")))
+            }
+            CONVERT_LONG => convert = true,
+            OUTPUT_SHORT | OUTPUT_LONG => {
+                output = Some(PathBuf::from(option_value(&mut args, &arg, "output")))
+            }
+            SORT_LONG => order = parse_order(&option_value(&mut args, &arg, "sort")),
+            HELP_SHORT | HELP_LONG => {
+                println!(
+                    r#"Sum several statistic files into one
+
+Files must be collected with the same sections, {dwell_long} and {flight_long},
+unless {convert_long} is given
+
+{usage_title} {usage_content}
+
+{optiongs_title}
+    {output_short}, {output_long} {output_value}
+                    Output file, required
+
+    {convert_long}
+                    Keep only what every file counts: their common sections,
+                    dwell and flight times if all of them have those
+
+    {from_long} {format_value}
+                    Format of input files
+
+                    {default} by extension of each input file, {YAML_FORMAT} if unknown
+
+    {format_short}, {format_long} {format_value}
+                    Format of output file
+
+                    {default} by extension of output file, {YAML_FORMAT} if unknown
+
+    {sort_long} {sort_value}
+                    Order counts are written in, see capture {help_long}
+
+                    {default} {KEY_ORDER}
+
+    {help_short}, {help_long}
+                    This message"#,
+                    usage_title = "Usage:".green(),
+                    optiongs_title = "Options:".green(),
+                    usage_content = format!(
+                        "{program_name} {MERGE_COMMAND} [OPTIONS] {OUTPUT_SHORT} <output> <input>..."
+                    )
+                    .cyan(),
+                    default = "Default:".green(),
+                    dwell_long = DWELL_LONG.cyan(),
+                    flight_long = FLIGHT_LONG.cyan(),
+                    convert_long = CONVERT_LONG.cyan(),
+                    output_short = OUTPUT_SHORT.cyan(),
+                    output_long = OUTPUT_LONG.cyan(),
+                    output_value = "<path>".cyan(),
+                    from_long = FROM_LONG.cyan(),
+                    format_short = FORMAT_SHORT.cyan(),
+                    format_long = FORMAT_LONG.cyan(),
+                    format_value = format!("[{}]", format_names().join(" | ")).cyan(),
+                    sort_long = SORT_LONG.cyan(),
+                    sort_value = format!("[{KEY_ORDER} | {COUNT_ORDER}]").cyan(),
+                    help_short = HELP_SHORT.cyan(),
+                    help_long = HELP_LONG.cyan(),
+                );
+                std::process::exit(0);
+            }
+            _ if arg.starts_with('-') => {
+                println!("Unhandled option: {}", arg);
+                std::process::exit(1);
+            }
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    let (Some(output), false) = (output, inputs.is_empty()) else {
+        println!(
+            "{} {}",
+            "error: expected output and input files, see".red(),
+            format!("{program_name} {MERGE_COMMAND} {HELP_LONG}").cyan()
+        );
+        std::process::exit(1);
+    };
+
+    let files: Vec<(&PathBuf, KeyCounts)> = inputs
+        .iter()
+        .map(|path| {
+            let from = from.or_else(|| Format::from_path(path)).unwrap_or_default();
+            let key_counts =
+                KeyCounts::load_as(path, from).unwrap_or_else(|error| exit_with(error));
+            (path, key_counts)
+        })
+        .collect();
+
+    let config_of = |key_counts: &KeyCounts| key_counts.config.clone().unwrap_or_default();
+    let (first_path, first) = &files[0];
+    let mut config = config_of(first);
+
+    for (path, key_counts) in &files[1..] {
+        let other = config_of(key_counts);
+        if other.version != config.version {
+            println!(
+                "{} {:?} {} {} {}",
+                "warning!: file".yellow(),
+                path,
+                "was created by version".yellow(),
+                other.version,
+                format!("and {:?} by {}", first_path, config.version).yellow(),
+            );
+        }
+
+        if convert {
+            config = config.common(&other).unwrap_or_else(|| {
+                println!(
+                    "{} {:?} {} {:?}",
+                    "error: file".red(),
+                    path,
+                    "has no section in common with".red(),
+                    first_path
+                );
+                std::process::exit(1);
+            });
+        } else if let Err(error) = other.check(&config) {
+            println!(
+                "{} {:?} {} {:?}{}{}",
+                "error: file".red(),
+                path,
+                "was collected with other settings than".red(),
+                first_path,
+                ", they can be merged with ".red(),
+                CONVERT_LONG.cyan()
+            );
+            exit_with(error);
+        }
+    }
+
+    config.version = VERSION.into();
+    let mut merged = KeyCounts::new();
+    merged.order = order;
+    merged.set_config(config.clone());
+    let mut overflowed = Vec::new();
+    for (_, mut key_counts) in files {
+        if key_counts.config.is_none() {
+            key_counts.set_config(Config::default());
+        }
+        key_counts.restrict(config.clone());
+        let file_overflowed = merged
+            .merge(key_counts)
+            .unwrap_or_else(|error| exit_with(error));
+        for (mode, count_item) in file_overflowed {
+            if !overflowed.contains(&(mode, count_item.clone())) {
+                overflowed.push((mode, count_item));
+            }
+        }
+    }
+    for (mode, count_item) in &overflowed {
+        println!(
+            "{} {} {} {} {}",
+            "warning: count of".yellow(),
+            count_item_to_string(count_item),
+            "in".yellow(),
+            mode,
+            format!("is too large, kept at {}", u32::MAX).yellow()
+        );
+    }
+
+    let format = format
+        .or_else(|| Format::from_path(&output))
+        .unwrap_or_default();
+    merged
+        .save_as(&output, format)
+        .unwrap_or_else(|error| exit_with(error));
+
+    println!(
+        "{} {} {} {:?}",
+        "merged".green(),
+        inputs.len(),
+        "files to".green(),
+        output
+    );
+}
+
+/// `diff` command: compare two statistic files.
+fn diff(program_name: &str, mut args: impl Iterator<Item = String>) {
+    let mut from = None;
+    let mut section: Option<Mode> = None;
+    let mut top = DEFAULT_TOP;
+    let mut json = false;
+    let mut paths = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            FROM_LONG => from = Some(parse_format(&option_value(&mut args, &arg, "from"))),
+            SECTION_LONG => {
+                section = Some(parse_section(&option_value(&mut args, &arg, "section")))
+            }
+            TOP_LONG => top = parse_top(&option_value(&mut args, &arg, "top")),
+            JSON_LONG => json = true,
+            HELP_SHORT | HELP_LONG => {
+                println!(
+                    r#"Compare two statistic files, e.g. of two periods or two layouts
+
+Items are compared by their share of the section total,
+so files collected over periods of different length can be compared
+
+{usage_title} {usage_content}
+
+{optiongs_title}
+    {section_long} {section_value}
+                    Compare only this section
+
+                    {default} every section found in either file
+
+    {top_long} {top_value}
+                    How many items to list as added, removed and top movers
+
+                    {default} {DEFAULT_TOP}
+
+    {json_long}
+                    Print every change as JSON, for other programs
+
+    {from_long} {format_value}
+                    Format of input files
+
+                    {default} by extension of each input file, {YAML_FORMAT} if unknown
+
+    {help_short}, {help_long}
+                    This message"#,
+                    usage_title = "Usage:".green(),
+                    optiongs_title = "Options:".green(),
+                    usage_content =
+                        format!("{program_name} {DIFF_COMMAND} [OPTIONS] <before> <after>").cyan(),
+                    default = "Default:".green(),
+                    section_long = SECTION_LONG.cyan(),
+                    section_value = "<section>".cyan(),
+                    top_long = TOP_LONG.cyan(),
+                    top_value = "<n>".cyan(),
+                    json_long = JSON_LONG.cyan(),
+                    from_long = FROM_LONG.cyan(),
+                    format_value = format!("[{}]", format_names().join(" | ")).cyan(),
+                    help_short = HELP_SHORT.cyan(),
+                    help_long = HELP_LONG.cyan(),
+                );
+                std::process::exit(0);
+            }
+            _ if arg.starts_with('-') => {
+                println!("Unhandled option: {}", arg);
+                std::process::exit(1);
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [before, after] = paths.as_slice() else {
+        println!(
+            "{} {}",
+            "error: expected two statistic files, see".red(),
+            format!("{program_name} {DIFF_COMMAND} {HELP_LONG}").cyan()
+        );
+        std::process::exit(1);
+    };
+
+    let load = |path: &PathBuf| {
+        let from = from.or_else(|| Format::from_path(path)).unwrap_or_default();
+        KeyCounts::load_as(path, from).unwrap_or_else(|error| exit_with(error))
+    };
+    let sections: Vec<SectionDiff> = diff::diff(&load(before), &load(after))
+        .into_iter()
+        .filter(|diff| section.map_or(true, |mode| diff.mode == mode))
+        .collect();
+
+    if json {
+        println!("{}", diff::to_json(&sections, top));
+        return;
+    }
+
+    if sections.is_empty() {
+        println!("{}", "nothing to compare".yellow());
+    }
+    for section in &sections {
+        println!(
+            "{} {} {} {} {}",
+            section.mode.to_string().green(),
+            section.total_before,
+            "→".green(),
+            section.total_after,
+            "counted".green()
+        );
+
+        print_changes("added", section.added(), top, |change| {
+            format!("{}", change.after)
+        });
+        print_changes("removed", section.removed(), top, |change| {
+            format!("{}", change.before)
+        });
+        print_changes("top movers", section.top_movers(top), top, |change| {
+            let relative = change
+                .relative()
+                .map(|relative| format!(", {:+.1}%", relative * 100.0))
+                .unwrap_or_default();
+            let share_change = format!("{:+.2} pp", change.share_change());
+            format!(
+                "{} → {} ({:+}{}) {}",
+                change.before,
+                change.after,
+                change.delta(),
+                relative,
+                if change.share_change() > 0.0 {
+                    share_change.green()
+                } else {
+                    share_change.red()
+                }
+            )
+        });
+        println!();
+    }
+}
+
+/// Print up to `top` of `changes` under `title`, each with its `details`.
+fn print_changes<'a>(
+    title: &str,
+    changes: impl Iterator<Item = &'a Change>,
+    top: usize,
+    details: impl Fn(&Change) -> String,
 ) {
-    let error = format!(
-        "Config in output file that you provide {:?} do not match to your options\nit is means different settings were used to create this file\n",
-        path
-    ).red();
-    if config.pairs != pairs {
+    let changes: Vec<&Change> = changes.collect();
+    if changes.is_empty() {
+        return;
+    }
+
+    println!("  {} ({})", title.cyan(), changes.len());
+    for change in changes.iter().take(top) {
         println!(
-            "{error}{details_title} {pairs_short} {or} {pairs_long} {is} {pairs} {when_in_file} {config_pairs}",
-            details_title="Details:".red(),
-            pairs_short = PAIRS_SHORT.cyan(),
-            or="or".red(),
-            pairs_long=PAIRS_LONG.cyan(),
-            is="is".red(),
-            pairs=pairs.to_string().cyan(),
-            when_in_file="when in file".red(),
-            config_pairs = config.pairs.to_string().cyan(),
+            "    {}  {}",
+            count_item_to_string(&change.item),
+            details(change)
+        );
+    }
+    if changes.len() > top {
+        println!("    {} {}", "and".cyan(), changes.len() - top);
+    }
+}
+
+/// `report` command: print the most frequent items of a statistic file.
+fn report(program_name: &str, mut args: impl Iterator<Item = String>) {
+    let mut from = None;
+    let mut section: Option<Mode> = None;
+    let mut top = DEFAULT_TOP;
+    let mut plain = false;
+    let mut input: Option<PathBuf> = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            FROM_LONG => from = Some(parse_format(&option_value(&mut args, &arg, "from"))),
+            SECTION_LONG => {
+                section = Some(parse_section(&option_value(&mut args, &arg, "section")))
+            }
+            TOP_LONG => top = parse_top(&option_value(&mut args, &arg, "top")),
+            PLAIN_SHORT | PLAIN_LONG => plain = true,
+            HELP_SHORT | HELP_LONG => {
+                println!(
+                    r#"Print the most frequent items of each section of a statistic file
+with their count, share of the section total and cumulative share,
+and how often each combination of modifiers is held
+
+{usage_title} {usage_content}
+
+{optiongs_title}
+    {section_long} {section_value}
+                    Report only this section
+
+                    {default} every section of the file
+
+    {top_long} {top_value}
+                    How many items to list
+
+                    {default} {DEFAULT_TOP}
+
+    {plain_short}, {plain_long}
+                    Plain text without colors
+
+    {from_long} {format_value}
+                    Format of input file
+
+                    {default} by extension of input file, {YAML_FORMAT} if unknown
+
+    {help_short}, {help_long}
+                    This message"#,
+                    usage_title = "Usage:".green(),
+                    optiongs_title = "Options:".green(),
+                    usage_content =
+                        format!("{program_name} {REPORT_COMMAND} [OPTIONS] <input>").cyan(),
+                    default = "Default:".green(),
+                    section_long = SECTION_LONG.cyan(),
+                    section_value = "<section>".cyan(),
+                    top_long = TOP_LONG.cyan(),
+                    top_value = "<n>".cyan(),
+                    plain_short = PLAIN_SHORT.cyan(),
+                    plain_long = PLAIN_LONG.cyan(),
+                    from_long = FROM_LONG.cyan(),
+                    format_value = format!("[{}]", format_names().join(" | ")).cyan(),
+                    help_short = HELP_SHORT.cyan(),
+                    help_long = HELP_LONG.cyan(),
+                );
+                std::process::exit(0);
+            }
+            _ if arg.starts_with('-') => {
+                println!("Unhandled option: {}", arg);
+                std::process::exit(1);
+            }
+            _ => input = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let Some(input) = input else {
+        println!(
+            "{} {}",
+            "error: expected input file, see".red(),
+            format!("{program_name} {REPORT_COMMAND} {HELP_LONG}").cyan()
         );
         std::process::exit(1);
+    };
+
+    if plain {
+        colored::control::set_override(false);
+    }
+
+    let from = from
+        .or_else(|| Format::from_path(&input))
+        .unwrap_or_default();
+    let key_counts = KeyCounts::load_as(&input, from).unwrap_or_else(|error| exit_with(error));
+
+    let mut modes: Vec<&Mode> = key_counts.sections.keys().collect();
+    modes.sort();
+    if let Some(ref section) = section {
+        modes.retain(|mode| *mode == section);
+    }
+    if modes.is_empty() {
+        println!("{}", "nothing to report".yellow());
     }
 
-    if config.no_chords != no_chords {
+    for mode in modes {
+        let counts = &key_counts.sections[mode];
+        let rows = report::ranking(counts);
         println!(
-            "{error}{details_title} {no_chords_long} {is} {no_chords} {when_in_file} {config_no_chords}",
-            details_title="Details:".red(),
-            no_chords_long = NO_CHORDS_LONG.cyan(),
-            is="is".red(),
-            no_chords=no_chords.to_string().cyan(),
-            when_in_file="when in file".red(),
-            config_no_chords = config.no_chords.to_string().cyan(),
+            "{} {} {} {} {}",
+            mode.to_string().green(),
+            key_counts.total(mode),
+            "counted,".green(),
+            rows.len(),
+            "different".green()
+        );
+
+        let rows: Vec<(String, &report::Row)> = rows
+            .iter()
+            .take(top)
+            .map(|row| (count_item_to_string(&row.item), row))
+            .collect();
+        let modifiers = report::modifier_breakdown(counts);
+        // one column for items and modifiers
+        let width = rows
+            .iter()
+            .map(|(name, _)| name.len())
+            .chain(modifiers.iter().map(|group| group.name().len()))
+            .max()
+            .unwrap_or(0)
+            .max(4);
+        println!(
+            "  {:>4}  {:<width$}  {:>8}  {:>7}  {:>10}",
+            "#".cyan(),
+            "item".cyan(),
+            "count".cyan(),
+            "share".cyan(),
+            "cumulative".cyan(),
+        );
+        for (rank, (name, row)) in rows.iter().enumerate() {
+            println!(
+                "  {:>4}  {}  {:>8}  {:>6.1}%  {:>9.1}%",
+                rank + 1,
+                format!("{:<width$}", name).bold(),
+                row.count,
+                row.share * 100.0,
+                row.cumulative * 100.0,
+            );
+        }
+
+        if modifiers.iter().any(|group| !group.modifiers.is_empty()) {
+            println!("  {}", "modifiers".cyan());
+            for group in &modifiers {
+                println!(
+                    "        {}  {:>8}  {:>6.1}%",
+                    format!("{:<width$}", group.name()).bold(),
+                    group.count,
+                    group.share * 100.0,
+                );
+            }
+        }
+        println!();
+    }
+}
+
+/// `heatmap` command: draw a keyboard shaded by how often each key is pressed.
+fn heatmap(program_name: &str, mut args: impl Iterator<Item = String>) {
+    let mut from = None;
+    let mut section: Option<Mode> = None;
+    let mut layout = Layout::default();
+    let mut layout_file: Option<PathBuf> = None;
+    let mut plain = false;
+    let mut input: Option<PathBuf> = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            FROM_LONG => from = Some(parse_format(&option_value(&mut args, &arg, "from"))),
+            SECTION_LONG => {
+                section = Some(parse_section(&option_value(&mut args, &arg, "section")))
+            }
+            LAYOUT_LONG => {
+                layout = parse_layout(&option_value(&mut args, &arg, "layout"));
+                layout_file = None;
+            }
+            LAYOUT_FILE_LONG => {
+                layout_file = Some(PathBuf::from(option_value(&mut args, &arg, "layout file")))
+            }
+            PLAIN_SHORT | PLAIN_LONG => plain = true,
+            HELP_SHORT | HELP_LONG => {
+                println!(
+                    r#"Draw a keyboard with each key shaded by its share of presses,
+from the least pressed in {blue} to the most pressed in {red}
+
+{usage_title} {usage_content}
+
+{optiongs_title}
+    {layout_long} {layout_value}
+                    Keyboard to draw
+
+                    {ANSI_LAYOUT} - row-staggered, US style
+                    {ISO_LAYOUT} - row-staggered, with a two row Enter
+                    {ORTHO_LAYOUT} - ortholinear, a grid of 12 columns
+                    {SPLIT_LAYOUT} - two halves with keys for the thumbs
+
+                    {default} {ANSI_LAYOUT}
+
+    {layout_file_long} {file_value}
+                    File with a keyboard to draw, one line per row of keys named
+                    as in statistics files, each optionally followed by
+                    :<width> in key widths; _ is a gap
+
+    {section_long} {section_value}
+                    Section presses are counted from, every key of every input
+                    of its items is a press
+
+                    {default} {KEYS_SECTION}, {CHORDS_SECTION} if the file has no {KEYS_SECTION}
+
+    {plain_short}, {plain_long}
+                    Plain text without colors
+
+    {from_long} {format_value}
+                    Format of input file
+
+                    {default} by extension of input file, {YAML_FORMAT} if unknown
+
+    {help_short}, {help_long}
+                    This message"#,
+                    blue = "blue".blue(),
+                    red = "red".red(),
+                    usage_title = "Usage:".green(),
+                    optiongs_title = "Options:".green(),
+                    usage_content =
+                        format!("{program_name} {HEATMAP_COMMAND} [OPTIONS] <input>").cyan(),
+                    default = "Default:".green(),
+                    layout_long = LAYOUT_LONG.cyan(),
+                    layout_value =
+                        format!("[{ANSI_LAYOUT} | {ISO_LAYOUT} | {ORTHO_LAYOUT} | {SPLIT_LAYOUT}]")
+                            .cyan(),
+                    layout_file_long = LAYOUT_FILE_LONG.cyan(),
+                    file_value = "<file>".cyan(),
+                    section_long = SECTION_LONG.cyan(),
+                    section_value = "<section>".cyan(),
+                    plain_short = PLAIN_SHORT.cyan(),
+                    plain_long = PLAIN_LONG.cyan(),
+                    from_long = FROM_LONG.cyan(),
+                    format_value = format!("[{}]", format_names().join(" | ")).cyan(),
+                    help_short = HELP_SHORT.cyan(),
+                    help_long = HELP_LONG.cyan(),
+                );
+                std::process::exit(0);
+            }
+            _ if arg.starts_with('-') => {
+                println!("Unhandled option: {}", arg);
+                std::process::exit(1);
+            }
+            _ => input = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let Some(input) = input else {
+        println!(
+            "{} {}",
+            "error: expected input file, see".red(),
+            format!("{program_name} {HEATMAP_COMMAND} {HELP_LONG}").cyan()
         );
         std::process::exit(1);
+    };
+
+    if plain {
+        colored::control::set_override(false);
+    }
+
+    let from = from
+        .or_else(|| Format::from_path(&input))
+        .unwrap_or_default();
+    let key_counts = KeyCounts::load_as(&input, from).unwrap_or_else(|error| exit_with(error));
+
+    let mode = section.or_else(|| {
+        let mut modes: Vec<&Mode> = key_counts.sections.keys().collect();
+        modes.sort_by_key(|mode| (mode.ngram, mode.chords));
+        modes.first().map(|mode| **mode)
+    });
+    let Some((mode, counts)) =
+        mode.and_then(|mode| key_counts.counts(&mode).map(|counts| (mode, counts)))
+    else {
+        println!("{}", "nothing to draw".yellow());
+        return;
+    };
+    let heatmap = Heatmap::new(counts);
+    let rows = match layout_file {
+        Some(path) => heatmap::load_layout(&path).unwrap_or_else(|error| exit_with(error)),
+        None => layout.rows(),
+    };
+
+    if stdout().is_terminal() {
+        if let Ok((columns, _)) = crossterm::terminal::size() {
+            if heatmap::columns(&rows) > columns as usize {
+                println!(
+                    "{}",
+                    "warning: terminal is narrower than the keyboard".yellow()
+                );
+            }
+        }
+    }
+
+    println!(
+        "{} {} {}",
+        mode.to_string().green(),
+        heatmap.total,
+        "presses".green()
+    );
+    let colors: &[Color] = if plain { &[] } else { &heatmap::SHADES };
+    for line in heatmap::render(&heatmap, &rows, colors) {
+        println!("{}", line);
+    }
+    if !plain {
+        println!(
+            "{} {}",
+            "shares:".green(),
+            heatmap::legend(&heatmap, colors).join(" ")
+        );
+    }
+
+    let missing = heatmap.missing(&rows);
+    if !missing.is_empty() {
+        let missing: Vec<String> = missing
+            .iter()
+            .map(|(key, count)| {
+                format!(
+                    "{} {} ({:.1}%)",
+                    keycode_to_string(key).bold(),
+                    count,
+                    heatmap.share(key) * 100.0
+                )
+            })
+            .collect();
+        println!("{} {}", "not on layout:".green(), missing.join(", "));
+    }
+}
+
+/// Exit code telling what kind of `error` stopped the program.
+fn exit_code(error: &Error) -> i32 {
+    match error {
+        Error::Aborted => EXIT_ABORTED,
+        Error::Io { .. } => EXIT_IO,
+        Error::Parse { .. } => EXIT_PARSE,
+        Error::ConfigMismatch { .. } => EXIT_CONFIG_MISMATCH,
+    }
+}
+
+/// Describe `error` to the user and exit with its [`exit_code`].
+fn exit_with(error: Error) -> ! {
+    match &error {
+        Error::Aborted => {}
+        Error::Io {
+            path: Some(path),
+            source,
+        } => println!(
+            "{} {:?}: {}",
+            "error: cannot access file".red(),
+            path,
+            source
+        ),
+        Error::Io { path: None, source } => {
+            println!("{} {}", "error: cannot use terminal:".red(), source)
+        }
+        Error::Parse { path, message } => println!(
+            "{} {:?} {}\n{}",
+            "error: data in file".red(),
+            path,
+            "not valid and cannot be deserialize".red(),
+            message
+        ),
+        Error::ConfigMismatch {
+            setting,
+            options,
+            file,
+        } => {
+            let option = match setting {
+                Setting::Collect => format!("{COLLECT_SHORT} {or} {COLLECT_LONG}", or = "or".red()),
+                Setting::Ngram => format!(
+                    "{PAIRS_SHORT} {or} {PAIRS_LONG} {or} {NGRAM_LONG}",
+                    or = "or".red()
+                ),
+                Setting::NoChords => NO_CHORDS_LONG.into(),
+                Setting::Dwell => DWELL_LONG.into(),
+                Setting::Flight => FLIGHT_LONG.into(),
+            };
+            println!(
+                "{details_title} {option} {is} {options} {when_in_file} {file}",
+                details_title = "Details:".red(),
+                option = option.cyan(),
+                is = "is".red(),
+                options = options.cyan(),
+                when_in_file = "when in file".red(),
+                file = file.cyan(),
+            );
+        }
     }
 
+    std::process::exit(exit_code(&error));
+}
+
+/// Warn when the output file was written by another version
+/// and ask to go on unless `force_modify_output`.
+fn check_version(config: &mut Config, force_modify_output: bool) -> Result<()> {
     if config.version != VERSION {
         println!(
             "{warning} {config_verison}{curent_is} {VERSION}",
@@ -840,28 +2358,15 @@ fn check_config(
         );
 
         if !force_modify_output {
-            print!("are you sure you want to modify this file? [y/N] ");
-            io::stdout().flush().unwrap();
-            let mut buffer = [0; 1];
-            io::stdin()
-                .read_exact(&mut buffer)
-                .or_else(|_| -> Result<_, ()> {
-                    println!("{}", "cannot read terminal input".red());
-                    std::process::exit(1);
-                })
-                .unwrap();
-            let character = buffer[0] as char;
-
-            match character {
-                'y' | 'Y' => {}
-                _ => std::process::exit(0),
-            }
+            confirm()?;
         }
         config.version = VERSION.into();
     }
+
+    Ok(())
 }
 
-fn ask_modify(force: bool, path: &PathBuf) {
+fn ask_modify(force: bool, path: &Path) -> Result<()> {
     if !force {
         println!(
             "{} ({:?}) {}",
@@ -869,22 +2374,25 @@ fn ask_modify(force: bool, path: &PathBuf) {
             path,
             "already exist".yellow(),
         );
-        print!("are you sure you want to modify this file? [y/N] ");
-        io::stdout().flush().unwrap();
-
-        let mut buffer = [0; 1];
-        io::stdin()
-            .read_exact(&mut buffer)
-            .or_else(|_| -> Result<_, ()> {
-                println!("{}", "cannot read terminal input".red());
-                std::process::exit(1);
-            })
-            .unwrap();
-        let character = buffer[0] as char;
+        confirm()?;
+    }
 
-        match character {
-            'y' | 'Y' => {}
-            _ => std::process::exit(0),
-        }
+    Ok(())
+}
+
+/// Ask whether to modify the file, anything but yes aborts.
+fn confirm() -> Result<()> {
+    print!("are you sure you want to modify this file? [y/N] ");
+    io::stdout().flush().unwrap();
+
+    let mut buffer = [0; 1];
+    io::stdin()
+        .read_exact(&mut buffer)
+        .map_err(|source| Error::Io { path: None, source })?;
+    let character = buffer[0] as char;
+
+    match character {
+        'y' | 'Y' => Ok(()),
+        _ => Err(Error::Aborted),
     }
 }
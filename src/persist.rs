@@ -0,0 +1,164 @@
+//! Crash-safe file replacement.
+//!
+//! A file is never truncated in place: new contents go to a temporary file
+//! next to it, which is synced and renamed over the old one, so a crash
+//! leaves either the old or the new version.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Replace the file at `path` with `contents` atomically.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temporary = sibling(path, &format!(".{}.tmp", process::id()), true);
+
+    let result = (|| {
+        let mut file = File::create(&temporary)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)?;
+        sync_dir(path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+/// Keep the current file at `path` as `<path>.1`, shifting older backups up
+/// to `<path>.<count>` and dropping the oldest one.
+pub fn rotate_backups(path: &Path, count: usize) -> io::Result<()> {
+    if count == 0 || !path.exists() {
+        return Ok(());
+    }
+
+    for index in (1..count).rev() {
+        let older = backup_path(path, index);
+        if older.exists() {
+            fs::rename(&older, backup_path(path, index + 1))?;
+        }
+    }
+
+    // the current file must stay in place in case capture fails later
+    let backup = backup_path(path, 1);
+    let _ = fs::remove_file(&backup);
+    link_or_copy(path, &backup, |path, backup| fs::hard_link(path, backup))
+}
+
+/// Make `backup` a hard link to `path` by `link`, or a copy of it
+/// where the file system does not support links.
+fn link_or_copy(
+    path: &Path,
+    backup: &Path,
+    link: impl Fn(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    link(path, backup).or_else(|_| fs::copy(path, backup).map(|_| ()))
+}
+
+/// Path of the `index`-th backup of `path`, 1 being the newest.
+pub fn backup_path(path: &Path, index: usize) -> PathBuf {
+    sibling(path, &format!(".{}", index), false)
+}
+
+/// `path` with `suffix` appended to its file name, hidden when `hidden`.
+fn sibling(path: &Path, suffix: &str, hidden: bool) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = if hidden {
+        format!(".{}{}", name, suffix)
+    } else {
+        format!("{}{}", name, suffix)
+    };
+
+    path.with_file_name(name)
+}
+
+/// Make the rename in the directory of `path` durable.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TempFile;
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn backups_rotate() {
+        let path = TempFile::new("rotated.yaml");
+        let backups: Vec<TempFile> = (1..=4)
+            .map(|index| TempFile::at(backup_path(&path, index)))
+            .collect();
+
+        // nothing to keep yet
+        rotate_backups(&path, 3).unwrap();
+        assert!(!backups[0].exists());
+
+        for version in ["1", "2", "3", "4"] {
+            rotate_backups(&path, 3).unwrap();
+            write_atomic(&path, version.as_bytes()).unwrap();
+        }
+
+        assert_eq!(read(&path), "4");
+        assert_eq!(read(&backups[0]), "3");
+        assert_eq!(read(&backups[1]), "2");
+        assert_eq!(read(&backups[2]), "1");
+        assert!(!backups[3].exists());
+    }
+
+    #[test]
+    fn no_backups() {
+        let path = TempFile::new("not-rotated.yaml");
+        let backup = TempFile::at(backup_path(&path, 1));
+        write_atomic(&path, b"1").unwrap();
+
+        rotate_backups(&path, 0).unwrap();
+        assert!(!backup.exists());
+    }
+
+    #[test]
+    fn failed_write_keeps_file() {
+        let path = TempFile::new("kept.yaml");
+        write_atomic(&path, b"old").unwrap();
+        // the temporary file cannot be created where a directory is
+        let temporary = TempFile::at(sibling(&path, &format!(".{}.tmp", process::id()), true));
+        fs::create_dir(&temporary).unwrap();
+
+        assert!(write_atomic(&path, b"new").is_err());
+        assert_eq!(read(&path), "old");
+    }
+
+    #[test]
+    fn backup_copied_without_links() {
+        let path = TempFile::new("copied.yaml");
+        let backup = TempFile::at(backup_path(&path, 1));
+        fs::write(&path, "old").unwrap();
+
+        link_or_copy(&path, &backup, |_, _| {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "no links"))
+        })
+        .unwrap();
+        // written in place, which a link would follow
+        fs::write(&path, "new").unwrap();
+
+        assert_eq!(read(&backup), "old");
+    }
+}
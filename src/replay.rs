@@ -0,0 +1,314 @@
+//! Rebuilding statistics from a trace file.
+//!
+//! Trace steps are turned back into key events and fed through [`Capture`],
+//! so counts come out as if the trace was typed again under any modes.
+//!
+//! Text and plain traces only hold inputs: a key that stays in the next input
+//! is taken as still held, the others as let go right before it, so releases
+//! (and [`Capture::dwell`]) are not known. Plain traces have no times either,
+//! their inputs are taken a millisecond apart. JSON traces keep every press
+//! and release with its time, so they replay exactly.
+//!
+//! Several capture sessions appended to one trace are replayed as separate
+//! sessions, see [`Capture::start_session`].
+
+use crate::capture::Capture;
+use crate::error::Result;
+use crate::source::{KeyEvent, KeyEventKind};
+use crate::trace::{read_events, read_trace, TraceEvent, TraceFormat, TraceStep};
+use device_query::Keycode;
+use std::path::Path;
+use std::time::Duration;
+
+/// Time between inputs of a plain trace.
+const PLAIN_STEP: Duration = Duration::from_millis(1);
+
+/// Feed the trace at `path` written in `format` through `capture`.
+///
+/// Returns how many trace steps (or events, in JSON) were replayed.
+pub fn replay(path: &Path, format: TraceFormat, capture: &mut Capture) -> Result<usize> {
+    let mut replayer = Replayer::default();
+
+    if format == TraceFormat::Json {
+        let events = read_events(path)?;
+        let replayed = events.len();
+        for event in events {
+            let events = replayer.event(event);
+            replayer.feed(events, capture)?;
+        }
+        return Ok(replayed);
+    }
+
+    let steps = read_trace(path, format == TraceFormat::Plain)?;
+    let replayed = steps.len();
+    for step in steps {
+        let events = match step {
+            TraceStep::First(input) => replayer.input(input, None),
+            TraceStep::Regular(input, _) if format == TraceFormat::Plain => {
+                replayer.input(input, Some(PLAIN_STEP))
+            }
+            TraceStep::Regular(input, after) => replayer.input(input, Some(after)),
+            TraceStep::Empty => continue,
+        };
+        replayer.feed(events, capture)?;
+    }
+
+    Ok(replayed)
+}
+
+/// Keys held between trace steps, and the time reached.
+#[derive(Default)]
+struct Replayer {
+    held: Vec<Keycode>,
+    time: Duration,
+    /// Where the time of the current capture session starts,
+    /// a trace file may hold several sessions one after another.
+    session_start: Duration,
+    /// Whether the events made last start a new capture session.
+    session_started: bool,
+}
+
+impl Replayer {
+    /// Hand `events` to `capture`, telling it first when they start a new session.
+    fn feed(&mut self, events: Vec<KeyEvent>, capture: &mut Capture) -> Result<()> {
+        if std::mem::take(&mut self.session_started) {
+            capture.start_session();
+        }
+        capture.update(events)
+    }
+
+    /// Events making `input` held, `after` the previous one;
+    /// `None` starts a new capture session.
+    fn input(&mut self, input: Vec<Keycode>, after: Option<Duration>) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+
+        let time = match after {
+            Some(after) => self.time + after,
+            None => {
+                // one second apart, so nothing is counted as held across sessions
+                let time = self.time + Duration::from_secs(1);
+                events.extend(self.release_all(self.time));
+                self.session_started = true;
+                time
+            }
+        };
+
+        // the same keys again were let go and pressed anew
+        if input.iter().all(|key| self.held.contains(key)) {
+            events.extend(self.release_all(time));
+        }
+        let released: Vec<Keycode> = self
+            .held
+            .iter()
+            .filter(|key| !input.contains(key))
+            .copied()
+            .collect();
+        for key in released {
+            self.held.retain(|held| *held != key);
+            events.push(KeyEvent::release(key, time));
+        }
+        for key in input {
+            if !self.held.contains(&key) {
+                self.held.push(key);
+                events.push(KeyEvent::press(key, time));
+            }
+        }
+
+        self.time = time;
+        events
+    }
+
+    /// Events of a JSON trace line.
+    fn event(&mut self, event: TraceEvent) -> Vec<KeyEvent> {
+        let TraceEvent {
+            kind, keys, time, ..
+        } = event;
+        let mut events = Vec::new();
+
+        // time going back means a new session appended to the same file
+        if self.session_start + time < self.time {
+            events.extend(self.release_all(self.time));
+            self.session_start = self.time + Duration::from_secs(1);
+            self.session_started = true;
+        }
+        let time = self.session_start + time;
+
+        match kind {
+            KeyEventKind::Press => {
+                let mut pressed: Vec<Keycode> = keys
+                    .iter()
+                    .filter(|key| !self.held.contains(key))
+                    .copied()
+                    .collect();
+                // nothing new means the release was not traced
+                if pressed.is_empty() {
+                    events.extend(keys.iter().map(|key| KeyEvent::release(*key, time)));
+                    self.held.retain(|key| !keys.contains(key));
+                    pressed = keys;
+                }
+                self.held.extend(&pressed);
+                events.extend(pressed.into_iter().map(|key| KeyEvent::press(key, time)));
+            }
+            KeyEventKind::Release => {
+                self.held.retain(|key| !keys.contains(key));
+                events.extend(keys.into_iter().map(|key| KeyEvent::release(key, time)));
+            }
+        }
+
+        self.time = time;
+        events
+    }
+
+    fn release_all(&mut self, time: Duration) -> Vec<KeyEvent> {
+        self.held
+            .drain(..)
+            .map(|key| KeyEvent::release(key, time))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counts::{CountItem, KeyCounts, Mode};
+    use crate::temp::TempFile;
+    use std::fs;
+
+    fn chords() -> Mode {
+        Mode::new(1, true)
+    }
+
+    fn chord_pairs() -> Mode {
+        Mode::new(2, true)
+    }
+
+    fn single(keys: &[Keycode]) -> CountItem {
+        CountItem::Single(keys.to_vec())
+    }
+
+    fn pair(first: &[Keycode], second: &[Keycode]) -> CountItem {
+        CountItem::Pair(first.to_vec(), second.to_vec())
+    }
+
+    /// Counts of chords and chord pairs with flight and dwell times
+    /// replayed from `trace` written in `format`.
+    fn replayed(name: &str, trace: &str, format: TraceFormat) -> (usize, KeyCounts) {
+        let path = TempFile::new(name);
+        fs::write(&path, trace).unwrap();
+        let mut capture = Capture::new(KeyCounts::new(), vec![chords(), chord_pairs()]);
+        capture.flight = true;
+        capture.dwell = true;
+
+        let replayed = replay(&path, format, &mut capture).unwrap();
+        capture.flush().unwrap();
+        (replayed, capture.key_counts)
+    }
+
+    /// Mean flight time of `count_item` in milliseconds.
+    fn flight_ms(key_counts: &KeyCounts, count_item: &CountItem) -> f64 {
+        key_counts.flight[&chord_pairs()][count_item].mean_ms
+    }
+
+    #[test]
+    fn text_trace() {
+        let trace = "LShift\nA+LShift after 10ms\nB after 120ms\nC\nD after 50ms\n";
+        let (replayed, key_counts) = replayed("replay-trace.txt", trace, TraceFormat::Text);
+
+        assert_eq!(replayed, 5);
+        for input in [
+            &[Keycode::LShift][..],
+            &[Keycode::A, Keycode::LShift],
+            &[Keycode::B],
+            &[Keycode::C],
+            &[Keycode::D],
+        ] {
+            assert_eq!(key_counts.get(&chords(), &single(input)), 1, "{:?}", input);
+        }
+        assert_eq!(key_counts.total(&chords()), 5);
+
+        let shifted_a = &[Keycode::A, Keycode::LShift][..];
+        let within_sessions = [
+            (pair(&[Keycode::LShift], shifted_a), 10.0),
+            (pair(shifted_a, &[Keycode::B]), 120.0),
+            (pair(&[Keycode::C], &[Keycode::D]), 50.0),
+        ];
+        for (count_item, flight) in &within_sessions {
+            assert_eq!(key_counts.get(&chord_pairs(), count_item), 1);
+            assert_eq!(flight_ms(&key_counts, count_item), *flight);
+        }
+        // B and C are in different sessions
+        assert_eq!(key_counts.total(&chord_pairs()), 3);
+        assert_eq!(key_counts.flight[&chord_pairs()].len(), 3);
+    }
+
+    #[test]
+    fn plain_trace() {
+        let (replayed, key_counts) =
+            replayed("replay-trace-plain.txt", "A B A B ", TraceFormat::Plain);
+
+        assert_eq!(replayed, 4);
+        assert_eq!(key_counts.get(&chords(), &single(&[Keycode::A])), 2);
+        assert_eq!(key_counts.get(&chords(), &single(&[Keycode::B])), 2);
+        let a_b = pair(&[Keycode::A], &[Keycode::B]);
+        assert_eq!(key_counts.get(&chord_pairs(), &a_b), 2);
+        assert_eq!(
+            key_counts.get(&chord_pairs(), &pair(&[Keycode::B], &[Keycode::A])),
+            1
+        );
+        assert_eq!(
+            flight_ms(&key_counts, &a_b),
+            PLAIN_STEP.as_secs_f64() * 1000.0
+        );
+    }
+
+    #[test]
+    fn json_trace() {
+        let line = |event: &str, keys: &str, time_ms: u64| {
+            format!(
+                r#"{{"event":"{event}","keys":[{keys}],"time_ms":{time_ms}.0,"wall_ms":0,"delta_ms":0.0}}"#
+            )
+        };
+        let trace = [
+            line("press", r#""LShift""#, 0),
+            line("press", r#""A","LShift""#, 10),
+            line("release", r#""A""#, 15),
+            line("release", r#""LShift""#, 20),
+            line("press", r#""B""#, 140),
+            line("release", r#""B""#, 170),
+            // a second capture session
+            line("press", r#""C""#, 0),
+            line("release", r#""C""#, 30),
+            line("press", r#""D""#, 50),
+        ]
+        .join("\n");
+        let (replayed, key_counts) = replayed("replay-trace.jsonl", &trace, TraceFormat::Json);
+
+        assert_eq!(replayed, 9);
+        assert_eq!(key_counts.total(&chords()), 5);
+        assert_eq!(
+            key_counts.get(&chords(), &single(&[Keycode::A, Keycode::LShift])),
+            1
+        );
+
+        let dwell_ms = |key| key_counts.dwell[&key].mean_ms;
+        assert_eq!(dwell_ms(Keycode::A), 5.0);
+        assert_eq!(dwell_ms(Keycode::LShift), 20.0);
+        assert_eq!(dwell_ms(Keycode::B), 30.0);
+        assert_eq!(dwell_ms(Keycode::C), 30.0);
+
+        let shifted_a = &[Keycode::A, Keycode::LShift][..];
+        assert_eq!(
+            flight_ms(&key_counts, &pair(&[Keycode::LShift], shifted_a)),
+            10.0
+        );
+        assert_eq!(
+            flight_ms(&key_counts, &pair(shifted_a, &[Keycode::B])),
+            130.0
+        );
+        assert_eq!(
+            flight_ms(&key_counts, &pair(&[Keycode::C], &[Keycode::D])),
+            50.0
+        );
+        assert_eq!(key_counts.total(&chord_pairs()), 3);
+    }
+}
@@ -0,0 +1,174 @@
+//! Frequency ranking of the items of a section.
+
+use crate::counts::{count_item_to_string, input_to_string, CountItem, HashMap};
+use crate::keycode;
+use device_query::Keycode;
+use std::cmp::Reverse;
+
+/// One item of a [`ranking`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub item: CountItem,
+    pub count: u32,
+    /// Share of the section total, from 0 to 1.
+    pub share: f64,
+    /// Share of this item and every more frequent one.
+    pub cumulative: f64,
+}
+
+/// `count` as a share of `total` from 0 to 1, 0 when nothing was counted at all.
+fn share(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+/// Every item of `counts`, the most frequent first.
+pub fn ranking(counts: &HashMap<CountItem, u32>) -> Vec<Row> {
+    let total: u64 = counts.values().map(|count| *count as u64).sum();
+
+    let mut items: Vec<(&CountItem, u32)> =
+        counts.iter().map(|(item, count)| (item, *count)).collect();
+    items.sort_by_cached_key(|(item, count)| (Reverse(*count), count_item_to_string(item)));
+
+    let mut cumulative = 0;
+    items
+        .into_iter()
+        .map(|(item, count)| {
+            cumulative += count as u64;
+            Row {
+                item: item.clone(),
+                count,
+                share: share(count as u64, total),
+                cumulative: share(cumulative, total),
+            }
+        })
+        .collect()
+}
+
+/// How often one combination of modifiers was held, see [`modifier_breakdown`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModifierShare {
+    /// Modifiers held in any input of the items, empty for items without any.
+    pub modifiers: Vec<Keycode>,
+    pub count: u64,
+    pub share: f64,
+}
+
+impl ModifierShare {
+    /// Modifiers joined with `+`, or `none`.
+    pub fn name(&self) -> String {
+        if self.modifiers.is_empty() {
+            "none".into()
+        } else {
+            input_to_string(&self.modifiers)
+        }
+    }
+}
+
+/// Counts of `counts` grouped by the modifiers their items hold, the most frequent first.
+pub fn modifier_breakdown(counts: &HashMap<CountItem, u32>) -> Vec<ModifierShare> {
+    let total: u64 = counts.values().map(|count| *count as u64).sum();
+
+    let mut groups: HashMap<Vec<Keycode>, u64> = HashMap::new();
+    for (item, count) in counts {
+        let mut modifiers: Vec<Keycode> = Vec::new();
+        for key in item.inputs().into_iter().flatten() {
+            if keycode::is_modifier(key) && !modifiers.contains(key) {
+                modifiers.push(*key);
+            }
+        }
+        keycode::sort_keys(&mut modifiers);
+        *groups.entry(modifiers).or_insert(0) += *count as u64;
+    }
+
+    let mut breakdown: Vec<ModifierShare> = groups
+        .into_iter()
+        .map(|(modifiers, count)| ModifierShare {
+            modifiers,
+            count,
+            share: share(count, total),
+        })
+        .collect();
+    breakdown.sort_by_cached_key(|group| (Reverse(group.count), group.name()));
+
+    breakdown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counts::parse_count_item;
+
+    fn counts(items: &[(&str, u32)]) -> HashMap<CountItem, u32> {
+        items
+            .iter()
+            .map(|(item, count)| (parse_count_item(item).unwrap(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn ranking_by_count_then_key() {
+        let rows = ranking(&counts(&[("C", 1), ("A", 2), ("B", 4), ("D", 1)]));
+
+        let ranked: Vec<(String, u32, f64, f64)> = rows
+            .iter()
+            .map(|row| {
+                (
+                    count_item_to_string(&row.item),
+                    row.count,
+                    row.share,
+                    row.cumulative,
+                )
+            })
+            .collect();
+        assert_eq!(
+            ranked,
+            [
+                ("B".into(), 4, 0.5, 0.5),
+                ("A".into(), 2, 0.25, 0.75),
+                ("C".into(), 1, 0.125, 0.875),
+                ("D".into(), 1, 0.125, 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn nothing_counted() {
+        assert!(ranking(&HashMap::new()).is_empty());
+        assert!(modifier_breakdown(&HashMap::new()).is_empty());
+
+        let rows = ranking(&counts(&[("A", 0)]));
+        assert_eq!((rows[0].share, rows[0].cumulative), (0.0, 0.0));
+        assert_eq!(modifier_breakdown(&counts(&[("A", 0)]))[0].share, 0.0);
+    }
+
+    #[test]
+    fn modifiers() {
+        let breakdown = modifier_breakdown(&counts(&[
+            ("A", 3),
+            ("B", 1),
+            ("A+LShift", 2),
+            ("LControl+LShift", 1),
+            ("C+LShift+LControl", 1),
+            // modifiers of every input of a pair
+            ("A+LShift, B+LShift", 1),
+            ("LShift", 1),
+        ]));
+
+        let groups: Vec<(String, u64, f64)> = breakdown
+            .iter()
+            .map(|group| (group.name(), group.count, group.share))
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                ("LShift".into(), 4, 0.4),
+                ("none".into(), 4, 0.4),
+                ("LControl+LShift".into(), 2, 0.2),
+            ]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use device_query::{DeviceQuery, DeviceState, Keycode};
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEventKind {
+    Press,
+    Release,
+}
+
+/// A key going down or up.
+///
+/// `time` is measured from the source's own starting point,
+/// so only the difference between two events is meaningful.
+/// Events with equal `time` happened simultaneously.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: Keycode,
+    pub kind: KeyEventKind,
+    pub time: Duration,
+}
+
+impl KeyEvent {
+    pub fn press(key: Keycode, time: Duration) -> Self {
+        Self {
+            key,
+            kind: KeyEventKind::Press,
+            time,
+        }
+    }
+
+    pub fn release(key: Keycode, time: Duration) -> Self {
+        Self {
+            key,
+            kind: KeyEventKind::Release,
+            time,
+        }
+    }
+}
+
+/// Keys held down at some moment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyState {
+    pub keys: Vec<Keycode>,
+    pub time: Duration,
+}
+
+impl KeyState {
+    pub fn new(keys: Vec<Keycode>, time: Duration) -> Self {
+        Self { keys, time }
+    }
+
+    /// Events that turn `previous` into `self`, all stamped with `self.time`.
+    pub fn events_since(&self, previous: &KeyState) -> Vec<KeyEvent> {
+        let released = previous
+            .keys
+            .iter()
+            .filter(|key| !self.keys.contains(key))
+            .map(|key| KeyEvent::release(*key, self.time));
+        let pressed = self
+            .keys
+            .iter()
+            .filter(|key| !previous.keys.contains(key))
+            .map(|key| KeyEvent::press(*key, self.time));
+
+        released.chain(pressed).collect()
+    }
+}
+
+/// Something that reports key presses and releases.
+pub trait KeySource {
+    /// Wait for the next events, which may be none if nothing happened for a while.
+    ///
+    /// Returns `None` when the source is exhausted and capture must stop.
+    fn poll(&mut self) -> Option<Vec<KeyEvent>>;
+}
+
+/// Polls the keyboard through [`device_query`] every `sensitivity`.
+///
+/// Events are stamped with the time of the poll that noticed them,
+/// so their precision is limited by `sensitivity`.
+pub struct DeviceQuerySource {
+    device_state: DeviceState,
+    sensitivity: Duration,
+    start: Instant,
+    first: bool,
+    last_state: KeyState,
+}
+
+impl DeviceQuerySource {
+    pub fn new(sensitivity: Duration) -> Self {
+        Self {
+            device_state: DeviceState::new(),
+            sensitivity,
+            start: Instant::now(),
+            first: true,
+            last_state: KeyState::default(),
+        }
+    }
+}
+
+impl KeySource for DeviceQuerySource {
+    fn poll(&mut self) -> Option<Vec<KeyEvent>> {
+        if self.first {
+            self.first = false;
+        } else {
+            thread::sleep(self.sensitivity);
+        }
+
+        let state = KeyState::new(self.device_state.get_keys(), self.start.elapsed());
+        let events = state.events_since(&self.last_state);
+        self.last_state = state;
+
+        Some(events)
+    }
+}
+
+/// Replays a prepared sequence of events, without touching any device.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedSource {
+    batches: VecDeque<Vec<KeyEvent>>,
+}
+
+impl ScriptedSource {
+    /// Every event is returned by its own [`KeySource::poll`].
+    pub fn new(events: impl IntoIterator<Item = KeyEvent>) -> Self {
+        Self {
+            batches: events.into_iter().map(|event| vec![event]).collect(),
+        }
+    }
+
+    /// Behave like a polling source that saw `states` one after another.
+    pub fn from_states(states: impl IntoIterator<Item = KeyState>) -> Self {
+        let mut last_state = KeyState::default();
+        let mut batches = VecDeque::new();
+        for state in states {
+            batches.push_back(state.events_since(&last_state));
+            last_state = state;
+        }
+
+        Self { batches }
+    }
+
+    pub fn push(&mut self, event: KeyEvent) {
+        self.batches.push_back(vec![event]);
+    }
+}
+
+impl KeySource for ScriptedSource {
+    fn poll(&mut self) -> Option<Vec<KeyEvent>> {
+        self.batches.pop_front()
+    }
+}
+
+/// Which [`KeySource`] the capture reads from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Poll the keyboard state through `device_query` (needs X on Linux).
+    #[default]
+    DeviceQuery,
+    /// Read key events from Linux event devices.
+    Evdev,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_since() {
+        let time = Duration::from_millis(50);
+        let previous = KeyState::new(vec![Keycode::LShift, Keycode::A], Duration::ZERO);
+        let state = KeyState::new(vec![Keycode::LShift, Keycode::B], time);
+
+        assert_eq!(
+            state.events_since(&previous),
+            [
+                KeyEvent::release(Keycode::A, time),
+                KeyEvent::press(Keycode::B, time),
+            ]
+        );
+        assert!(state.events_since(&state).is_empty());
+    }
+
+    #[test]
+    fn scripted_source_ends() {
+        let mut source = ScriptedSource::new([KeyEvent::press(Keycode::A, Duration::ZERO)]);
+        source.push(KeyEvent::release(Keycode::A, Duration::from_millis(10)));
+
+        assert_eq!(
+            source.poll(),
+            Some(vec![KeyEvent::press(Keycode::A, Duration::ZERO)])
+        );
+        assert_eq!(
+            source.poll(),
+            Some(vec![KeyEvent::release(
+                Keycode::A,
+                Duration::from_millis(10)
+            )])
+        );
+        assert_eq!(source.poll(), None);
+    }
+}
@@ -0,0 +1,505 @@
+//! SQLite storage, one database for the whole typing history.
+//!
+//! Tables:
+//!
+//! - `config`: the single [`Config`] row, `collect` as comma separated sections,
+//!   and [`KeyCounts::journal`]
+//! - `counts`: `section`, `item` (as [`count_item_to_string`]) and `count`
+//! - `dwell`: `key` and [`DurationStats`] columns
+//! - `flight`: `section`, `item` and [`DurationStats`] columns
+//! - `sessions`: every capture run, with wall clock `started_at` / `ended_at`
+//!   in milliseconds since the Unix epoch and how many `inputs` it captured
+//! - `trace`: every input of a session, `time_ms` since its first input
+//!   and `after_ms` since the previous one
+//!
+//! Histograms are stored as JSON arrays.
+
+use crate::counts::{
+    count_item_to_string, input_to_string, keycode_to_string, parse_count_item,
+    parse_keycode_from_string, Config, CountItem, KeyCounts, Mode,
+};
+use crate::error::{Error, Result};
+use crate::stats::{DurationStats, Percentiles};
+use device_query::Keycode;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS config (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    pairs INTEGER NOT NULL,
+    ngram INTEGER,
+    no_chords INTEGER NOT NULL,
+    collect TEXT NOT NULL,
+    dwell INTEGER NOT NULL,
+    flight INTEGER NOT NULL,
+    version TEXT NOT NULL,
+    journal INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS counts (
+    section TEXT NOT NULL,
+    item TEXT NOT NULL,
+    count INTEGER NOT NULL,
+    PRIMARY KEY (section, item)
+);
+CREATE TABLE IF NOT EXISTS dwell (
+    key TEXT PRIMARY KEY,
+    count INTEGER NOT NULL,
+    mean_ms REAL NOT NULL,
+    variance_ms2 REAL NOT NULL,
+    min_ms REAL NOT NULL,
+    max_ms REAL NOT NULL,
+    p50_ms REAL NOT NULL,
+    p90_ms REAL NOT NULL,
+    p99_ms REAL NOT NULL,
+    histogram TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS flight (
+    section TEXT NOT NULL,
+    item TEXT NOT NULL,
+    count INTEGER NOT NULL,
+    mean_ms REAL NOT NULL,
+    variance_ms2 REAL NOT NULL,
+    min_ms REAL NOT NULL,
+    max_ms REAL NOT NULL,
+    p50_ms REAL NOT NULL,
+    p90_ms REAL NOT NULL,
+    p99_ms REAL NOT NULL,
+    histogram TEXT NOT NULL,
+    PRIMARY KEY (section, item)
+);
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    started_at INTEGER NOT NULL,
+    ended_at INTEGER,
+    inputs INTEGER NOT NULL DEFAULT 0,
+    version TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS trace (
+    session INTEGER NOT NULL REFERENCES sessions (id),
+    position INTEGER NOT NULL,
+    input TEXT NOT NULL,
+    time_ms REAL NOT NULL,
+    after_ms REAL,
+    PRIMARY KEY (session, position)
+);
+";
+
+const STATS_COLUMNS: &str =
+    "count, mean_ms, variance_ms2, min_ms, max_ms, p50_ms, p90_ms, p99_ms, histogram";
+
+pub struct Database {
+    path: PathBuf,
+    connection: Connection,
+    /// Trace rows not written yet, see [`Database::flush_trace`].
+    trace: Vec<TraceRow>,
+    /// Position of the next trace row of each session.
+    positions: HashMap<i64, i64>,
+}
+
+struct TraceRow {
+    session: i64,
+    position: i64,
+    input: String,
+    time_ms: f64,
+    after_ms: Option<f64>,
+}
+
+impl Database {
+    /// Open the database at `path`, creating it and its tables when missing.
+    pub fn open(path: &Path) -> Result<Self> {
+        let database = Self {
+            path: path.to_path_buf(),
+            connection: Connection::open(path).map_err(|error| sql_error(path, error))?,
+            trace: Vec::new(),
+            positions: HashMap::new(),
+        };
+        database
+            .connection
+            .execute_batch(SCHEMA)
+            .map_err(|error| database.error(error))?;
+
+        Ok(database)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Everything counted so far, empty counts without config for a new database.
+    pub fn load(&self) -> Result<KeyCounts> {
+        self.read().map_err(|error| self.error(error))
+    }
+
+    /// Replace the stored config and statistics with `key_counts`.
+    pub fn save(&mut self, key_counts: &KeyCounts) -> Result<()> {
+        self.write(key_counts).map_err(|error| self.error(error))
+    }
+
+    /// Record that a capture starts now, returning the session id.
+    pub fn start_session(&mut self, version: &str) -> Result<i64> {
+        self.connection
+            .execute(
+                "INSERT INTO sessions (started_at, version) VALUES (?1, ?2)",
+                params![now_ms(), version],
+            )
+            .map_err(|error| self.error(error))?;
+
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    pub fn end_session(&mut self, session: i64, inputs: u64) -> Result<()> {
+        self.flush_trace()?;
+        self.connection
+            .execute(
+                "UPDATE sessions SET ended_at = ?1, inputs = ?2 WHERE id = ?3",
+                params![now_ms(), inputs as i64, session],
+            )
+            .map_err(|error| self.error(error))?;
+
+        Ok(())
+    }
+
+    /// Append one input of `session` pressed `time` after its first one
+    /// and `after` the previous one.
+    ///
+    /// Kept in memory until [`Database::flush_trace`], so typing does not
+    /// write to disk on every key.
+    pub fn append_trace(
+        &mut self,
+        session: i64,
+        input: &[Keycode],
+        time: Duration,
+        after: Option<Duration>,
+    ) -> Result<()> {
+        let position = match self.positions.get(&session) {
+            Some(position) => *position,
+            None => self
+                .connection
+                .query_row(
+                    "SELECT COUNT(*) FROM trace WHERE session = ?1",
+                    [session],
+                    |row| row.get(0),
+                )
+                .map_err(|error| self.error(error))?,
+        };
+        self.positions.insert(session, position + 1);
+
+        self.trace.push(TraceRow {
+            session,
+            position,
+            input: input_to_string(input),
+            time_ms: ms(time),
+            after_ms: after.map(ms),
+        });
+
+        Ok(())
+    }
+
+    /// Write every trace row appended since the last call, in one transaction.
+    pub fn flush_trace(&mut self) -> Result<()> {
+        if self.trace.is_empty() {
+            return Ok(());
+        }
+
+        self.write_trace().map_err(|error| self.error(error))?;
+        self.trace.clear();
+
+        Ok(())
+    }
+
+    fn write_trace(&mut self) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT INTO trace (session, position, input, time_ms, after_ms)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for row in &self.trace {
+                statement.execute(params![
+                    row.session,
+                    row.position,
+                    row.input,
+                    row.time_ms,
+                    row.after_ms
+                ])?;
+            }
+        }
+
+        transaction.commit()
+    }
+
+    fn read(&self) -> rusqlite::Result<KeyCounts> {
+        let mut key_counts = KeyCounts::new();
+
+        let config = self
+            .connection
+            .query_row(
+                "SELECT pairs, ngram, no_chords, collect, dwell, flight, version, journal
+                 FROM config",
+                [],
+                |row| {
+                    let collect: String = row.get(3)?;
+                    let config = Config {
+                        pairs: row.get(0)?,
+                        ngram: row.get::<_, Option<i64>>(1)?.map(|ngram| ngram as usize),
+                        no_chords: row.get(2)?,
+                        collect: collect
+                            .split(',')
+                            .filter(|section| !section.is_empty())
+                            .map(|section| section.parse().map_err(|error| conversion(3, error)))
+                            .collect::<rusqlite::Result<Vec<Mode>>>()?,
+                        dwell: row.get(4)?,
+                        flight: row.get(5)?,
+                        version: row.get(6)?,
+                    };
+                    Ok((config, row.get::<_, i64>(7)? as u64))
+                },
+            )
+            .optional()?;
+        if let Some((config, journal)) = config {
+            key_counts.config = Some(config);
+            key_counts.journal = journal;
+        }
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT section, item, count FROM counts")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let (mode, count_item) = section_item(row)?;
+            key_counts.counts_mut(mode).insert(count_item, row.get(2)?);
+        }
+
+        // sections without counts yet
+        for mode in key_counts.config.clone().unwrap_or_default().modes() {
+            key_counts.counts_mut(mode);
+        }
+
+        let mut statement = self
+            .connection
+            .prepare(&format!("SELECT key, {} FROM dwell", STATS_COLUMNS))?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let key: String = row.get(0)?;
+            let key = parse_keycode_from_string(&key).map_err(|error| conversion(0, error))?;
+            key_counts.dwell.insert(key, stats(row, 1)?);
+        }
+
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT section, item, {} FROM flight",
+            STATS_COLUMNS
+        ))?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let (mode, count_item) = section_item(row)?;
+            key_counts
+                .flight
+                .entry(mode)
+                .or_default()
+                .insert(count_item, stats(row, 2)?);
+        }
+
+        Ok(key_counts)
+    }
+
+    fn write(&mut self, key_counts: &KeyCounts) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+
+        transaction.execute("DELETE FROM config", [])?;
+        if let Some(ref config) = key_counts.config {
+            let collect = config
+                .collect
+                .iter()
+                .map(Mode::to_string)
+                .collect::<Vec<String>>()
+                .join(",");
+            transaction.execute(
+                "INSERT INTO config
+                 (id, pairs, ngram, no_chords, collect, dwell, flight, version, journal)
+                 VALUES (1, ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    config.pairs,
+                    config.ngram.map(|ngram| ngram as i64),
+                    config.no_chords,
+                    collect,
+                    config.dwell,
+                    config.flight,
+                    config.version,
+                    key_counts.journal as i64
+                ],
+            )?;
+        }
+
+        transaction.execute("DELETE FROM counts", [])?;
+        {
+            let mut statement = transaction
+                .prepare("INSERT INTO counts (section, item, count) VALUES (?1, ?2, ?3)")?;
+            for (mode, counts) in &key_counts.sections {
+                for (count_item, count) in counts {
+                    statement.execute(params![
+                        mode.to_string(),
+                        count_item_to_string(count_item),
+                        count
+                    ])?;
+                }
+            }
+        }
+
+        transaction.execute("DELETE FROM dwell", [])?;
+        {
+            let mut statement = transaction.prepare(&format!(
+                "INSERT INTO dwell (key, {}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                STATS_COLUMNS
+            ))?;
+            for (key, stats) in &key_counts.dwell {
+                statement.execute(params![
+                    keycode_to_string(key),
+                    stats.count as i64,
+                    stats.mean_ms,
+                    stats.variance_ms2,
+                    stats.min_ms,
+                    stats.max_ms,
+                    stats.percentiles.p50,
+                    stats.percentiles.p90,
+                    stats.percentiles.p99,
+                    histogram_to_json(&stats.histogram)
+                ])?;
+            }
+        }
+
+        transaction.execute("DELETE FROM flight", [])?;
+        {
+            let mut statement = transaction.prepare(&format!(
+                "INSERT INTO flight (section, item, {})
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                STATS_COLUMNS
+            ))?;
+            for (mode, flight) in &key_counts.flight {
+                for (count_item, stats) in flight {
+                    statement.execute(params![
+                        mode.to_string(),
+                        count_item_to_string(count_item),
+                        stats.count as i64,
+                        stats.mean_ms,
+                        stats.variance_ms2,
+                        stats.min_ms,
+                        stats.max_ms,
+                        stats.percentiles.p50,
+                        stats.percentiles.p90,
+                        stats.percentiles.p99,
+                        histogram_to_json(&stats.histogram)
+                    ])?;
+                }
+            }
+        }
+
+        transaction.commit()
+    }
+
+    fn error(&self, error: rusqlite::Error) -> Error {
+        sql_error(&self.path, error)
+    }
+}
+
+fn sql_error(path: &Path, error: rusqlite::Error) -> Error {
+    match error {
+        rusqlite::Error::FromSqlConversionFailure(..) => Error::parse(path, error),
+        _ => Error::io(path, io::Error::new(io::ErrorKind::Other, error)),
+    }
+}
+
+fn conversion(column: usize, error: String) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, error.into())
+}
+
+fn section_item(row: &Row) -> rusqlite::Result<(Mode, CountItem)> {
+    let section: String = row.get(0)?;
+    let item: String = row.get(1)?;
+
+    Ok((
+        section.parse().map_err(|error| conversion(0, error))?,
+        parse_count_item(&item).map_err(|error| conversion(1, error))?,
+    ))
+}
+
+/// [`DurationStats`] from [`STATS_COLUMNS`] starting at column `first`.
+fn stats(row: &Row, first: usize) -> rusqlite::Result<DurationStats> {
+    let histogram: String = row.get(first + 8)?;
+
+    Ok(DurationStats {
+        count: row.get::<_, i64>(first)? as u64,
+        mean_ms: row.get(first + 1)?,
+        variance_ms2: row.get(first + 2)?,
+        min_ms: row.get(first + 3)?,
+        max_ms: row.get(first + 4)?,
+        percentiles: Percentiles {
+            p50: row.get(first + 5)?,
+            p90: row.get(first + 6)?,
+            p99: row.get(first + 7)?,
+        },
+        histogram: serde_json::from_str(&histogram)
+            .map_err(|error| conversion(first + 8, error.to_string()))?,
+    })
+}
+
+fn histogram_to_json(histogram: &[u64]) -> String {
+    serde_json::to_string(histogram).expect("numbers always serialize")
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TempFile;
+
+    fn trace_positions(database: &Database, session: i64) -> Vec<(i64, String)> {
+        let mut statement = database
+            .connection
+            .prepare("SELECT position, input FROM trace WHERE session = ?1 ORDER BY position")
+            .unwrap();
+        let rows = statement
+            .query_map([session], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn trace_is_written_on_flush() {
+        let path = TempFile::new("trace.sqlite");
+        let mut database = Database::open(&path).unwrap();
+        let session = database.start_session("test").unwrap();
+        let ms = Duration::from_millis;
+
+        database
+            .append_trace(session, &[Keycode::A], Duration::ZERO, None)
+            .unwrap();
+        database
+            .append_trace(session, &[Keycode::B], ms(10), Some(ms(10)))
+            .unwrap();
+        assert!(trace_positions(&database, session).is_empty());
+
+        database.flush_trace().unwrap();
+        database
+            .append_trace(session, &[Keycode::C], ms(25), Some(ms(15)))
+            .unwrap();
+        database.end_session(session, 3).unwrap();
+
+        assert_eq!(
+            trace_positions(&database, session),
+            [(0, "A".into()), (1, "B".into()), (2, "C".into())]
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Summaries of key timings.
+
+use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Upper bounds (milliseconds, exclusive) of [`DurationStats::histogram`] buckets,
+/// the last bucket collects everything longer.
+pub const HISTOGRAM_BOUNDS_MS: [f64; 10] =
+    [25., 50., 75., 100., 125., 150., 200., 300., 500., 1000.];
+
+/// Percentiles estimated from the histogram, in milliseconds.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+/// Summary of a series of durations, in milliseconds.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DurationStats {
+    pub count: u64,
+    pub mean_ms: f64,
+    /// Population variance, in square milliseconds.
+    #[serde(default)]
+    pub variance_ms2: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    #[serde(default)]
+    pub percentiles: Percentiles,
+    /// Counts per [`HISTOGRAM_BOUNDS_MS`] bucket.
+    pub histogram: Vec<u64>,
+}
+
+impl Default for DurationStats {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            mean_ms: 0.,
+            variance_ms2: 0.,
+            min_ms: 0.,
+            max_ms: 0.,
+            percentiles: Percentiles::default(),
+            histogram: vec![0; HISTOGRAM_BOUNDS_MS.len() + 1],
+        }
+    }
+}
+
+impl DurationStats {
+    pub fn record(&mut self, duration: Duration) {
+        let ms = duration.as_secs_f64() * 1000.;
+
+        if self.count == 0 {
+            self.min_ms = ms;
+            self.max_ms = ms;
+        } else {
+            self.min_ms = self.min_ms.min(ms);
+            self.max_ms = self.max_ms.max(ms);
+        }
+
+        // Welford's online algorithm, the sum of squared deviations
+        // is recovered from the stored variance
+        let squares = self.variance_ms2 * self.count as f64;
+        self.count += 1;
+        let delta = ms - self.mean_ms;
+        self.mean_ms += delta / self.count as f64;
+        self.variance_ms2 = (squares + delta * (ms - self.mean_ms)) / self.count as f64;
+
+        // files written by hand or by other versions may have another bucket count
+        self.histogram.resize(HISTOGRAM_BOUNDS_MS.len() + 1, 0);
+        let bucket = HISTOGRAM_BOUNDS_MS
+            .iter()
+            .position(|bound| ms < *bound)
+            .unwrap_or(HISTOGRAM_BOUNDS_MS.len());
+        self.histogram[bucket] += 1;
+
+        self.update_percentiles();
+    }
+
+    /// Combine with stats of another series, as if all durations were recorded here.
+    pub fn merge(&mut self, other: &DurationStats) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other.clone();
+            return;
+        }
+
+        let count = (self.count + other.count) as f64;
+        let delta = other.mean_ms - self.mean_ms;
+        let squares = self.variance_ms2 * self.count as f64
+            + other.variance_ms2 * other.count as f64
+            + delta * delta * self.count as f64 * other.count as f64 / count;
+
+        self.mean_ms += delta * other.count as f64 / count;
+        self.variance_ms2 = squares / count;
+        self.count += other.count;
+        self.min_ms = self.min_ms.min(other.min_ms);
+        self.max_ms = self.max_ms.max(other.max_ms);
+
+        self.histogram.resize(HISTOGRAM_BOUNDS_MS.len() + 1, 0);
+        for (bucket, count) in self.histogram.iter_mut().zip(&other.histogram) {
+            *bucket += count;
+        }
+
+        self.update_percentiles();
+    }
+
+    fn update_percentiles(&mut self) {
+        self.percentiles = Percentiles {
+            p50: self.percentile(0.5),
+            p90: self.percentile(0.9),
+            p99: self.percentile(0.99),
+        };
+    }
+
+    /// Estimate the `rank` (0..=1) percentile by linear interpolation
+    /// inside the histogram bucket it falls into.
+    pub fn percentile(&self, rank: f64) -> f64 {
+        if self.count == 0 {
+            return 0.;
+        }
+
+        let target = rank * self.count as f64;
+        let mut seen = 0.;
+        for (bucket, count) in self.histogram.iter().enumerate() {
+            let count = *count as f64;
+            if count == 0. || seen + count < target {
+                seen += count;
+                continue;
+            }
+
+            let lower = match bucket {
+                0 => 0.,
+                _ => HISTOGRAM_BOUNDS_MS[bucket - 1],
+            }
+            .max(self.min_ms);
+            let upper = HISTOGRAM_BOUNDS_MS
+                .get(bucket)
+                .copied()
+                .unwrap_or(self.max_ms)
+                .min(self.max_ms);
+
+            return lower + (upper - lower) * (target - seen) / count;
+        }
+
+        self.max_ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorded(ms: &[u64]) -> DurationStats {
+        let mut stats = DurationStats::default();
+        for ms in ms {
+            stats.record(Duration::from_millis(*ms));
+        }
+        stats
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn record() {
+        let stats = recorded(&[10, 20, 30, 60]);
+
+        assert_eq!(stats.count, 4);
+        assert_close(stats.mean_ms, 30.);
+        assert_close(stats.variance_ms2, 350.);
+        assert_eq!((stats.min_ms, stats.max_ms), (10., 60.));
+        assert_eq!(stats.histogram[..3], [2, 1, 1]);
+        assert_eq!(stats.histogram.iter().sum::<u64>(), 4);
+    }
+
+    #[test]
+    fn merge_as_if_recorded_together() {
+        let all = [10, 20, 30, 60, 130, 700, 5];
+        let mut stats = recorded(&all[..3]);
+        stats.merge(&recorded(&all[3..]));
+        let expected = recorded(&all);
+
+        assert_eq!(stats.count, expected.count);
+        assert_close(stats.mean_ms, expected.mean_ms);
+        assert_close(stats.variance_ms2, expected.variance_ms2);
+        assert_eq!((stats.min_ms, stats.max_ms), (5., 700.));
+        assert_eq!(stats.histogram, expected.histogram);
+        assert_eq!(stats.percentiles, expected.percentiles);
+    }
+
+    #[test]
+    fn merge_empty() {
+        let some = recorded(&[10, 20]);
+
+        let mut stats = some.clone();
+        stats.merge(&DurationStats::default());
+        assert_eq!(stats, some);
+
+        let mut stats = DurationStats::default();
+        stats.merge(&some);
+        assert_eq!(stats, some);
+    }
+
+    #[test]
+    fn merge_other_bucket_count() {
+        let mut stats = recorded(&[10]);
+        stats.histogram.truncate(3);
+        let mut other = recorded(&[2000]);
+        other.histogram.truncate(2);
+
+        stats.merge(&recorded(&[2000]));
+        assert_eq!(stats.histogram.len(), HISTOGRAM_BOUNDS_MS.len() + 1);
+        assert_eq!(stats.histogram[HISTOGRAM_BOUNDS_MS.len()], 1);
+        // buckets missing in the other stats add nothing
+        stats.merge(&other);
+        assert_eq!(stats.histogram.iter().sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn percentiles_of_nothing() {
+        let stats = DurationStats::default();
+
+        assert_eq!(stats.percentile(0.5), 0.);
+        assert_eq!(stats.percentiles, Percentiles::default());
+    }
+
+    #[test]
+    fn percentiles_of_one_sample() {
+        let stats = recorded(&[42]);
+
+        for rank in [0., 0.5, 0.9, 1.] {
+            assert_eq!(stats.percentile(rank), 42.);
+        }
+    }
+
+    #[test]
+    fn percentiles_above_last_bound() {
+        let stats = recorded(&[1500, 3000]);
+
+        // the last bucket has no bound, it spans the shortest to the longest duration in it
+        assert_eq!(stats.histogram[HISTOGRAM_BOUNDS_MS.len()], 2);
+        assert_eq!(stats.percentile(0.5), 2250.);
+        assert_eq!(stats.percentile(1.), 3000.);
+        assert!(stats.percentiles.p99 <= 3000.);
+    }
+
+    #[test]
+    fn percentiles_interpolated() {
+        // 10 samples within 0..25 ms and 10 within 50..75 ms
+        let stats = recorded(&[[10; 10], [60; 10]].concat());
+
+        assert_eq!(stats.percentile(0.5), 25.);
+        assert_eq!(stats.percentile(0.25), 10. + (25. - 10.) * 0.5);
+        assert_eq!(stats.percentile(1.), 60.);
+    }
+}
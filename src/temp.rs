@@ -0,0 +1,55 @@
+//! Files written by tests.
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// Path in the temporary directory, unique to the test process and `name`.
+/// The file, or empty directory, is removed once this is dropped, also when the test panics.
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    pub fn new(name: &str) -> Self {
+        Self::at(std::env::temp_dir().join(format!(
+            "hectic-key-capture-{}-{}",
+            std::process::id(),
+            name
+        )))
+    }
+
+    /// Removed like a file of [`TempFile::new`], e.g. one written next to it.
+    pub fn at(path: PathBuf) -> Self {
+        // left over by a killed run with the same process id
+        remove(&path);
+
+        Self(path)
+    }
+}
+
+fn remove(path: &Path) {
+    if path.is_dir() {
+        let _ = fs::remove_dir(path);
+    } else {
+        let _ = fs::remove_file(path);
+    }
+}
+
+impl Deref for TempFile {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        remove(&self.0);
+    }
+}
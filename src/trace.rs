@@ -0,0 +1,389 @@
+//! Trace file: every input with the time since the previous one.
+//!
+//! Written in one of the [`TraceFormat`]s: readable lines (`A+LShift after 120ms`),
+//! plain space separated inputs, or JSON Lines with one [`TraceEvent`] per line:
+//!
+//! ```text
+//! {"event":"press","keys":["LShift"],"time_ms":0.0,"wall_ms":1792203139133,"delta_ms":0.0}
+//! {"event":"press","keys":["A","LShift"],"time_ms":10.0,"wall_ms":1792203139143,"delta_ms":10.0}
+//! {"event":"release","keys":["A"],"time_ms":15.0,"wall_ms":1792203139148,"delta_ms":5.0}
+//! ```
+
+use crate::counts::{
+    input_to_string, keycode_to_string, parse_input_from_string, parse_keycode_from_string,
+};
+use crate::error::{Error, Result};
+use crate::source::KeyEventKind;
+use core::fmt;
+use device_query::Keycode;
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const TEXT_TRACE_FORMAT: &str = "text";
+pub const PLAIN_TRACE_FORMAT: &str = "plain";
+pub const JSON_TRACE_FORMAT: &str = "json";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// `<input> after <duration>` lines.
+    #[default]
+    Text,
+    /// Inputs separated by spaces, without times.
+    Plain,
+    /// One JSON object per press and release.
+    Json,
+}
+
+impl TraceFormat {
+    /// Format told by the extension of `path`, if it is a known one.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "jsonl" | "ndjson" => Some(TraceFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceFormat::Text => write!(f, "{}", TEXT_TRACE_FORMAT),
+            TraceFormat::Plain => write!(f, "{}", PLAIN_TRACE_FORMAT),
+            TraceFormat::Json => write!(f, "{}", JSON_TRACE_FORMAT),
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            TEXT_TRACE_FORMAT => Ok(TraceFormat::Text),
+            PLAIN_TRACE_FORMAT => Ok(TraceFormat::Plain),
+            JSON_TRACE_FORMAT => Ok(TraceFormat::Json),
+            _ => Err(format!("{} is not a known trace format", s)),
+        }
+    }
+}
+
+/// One line (or word, in plain style) of a trace file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceStep {
+    First(Vec<Keycode>),
+    Regular(Vec<Keycode>, Duration),
+    Empty,
+}
+
+/// One line of a JSON Lines trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    pub kind: KeyEventKind,
+    /// Input made by a press, or keys let go by a release.
+    pub keys: Vec<Keycode>,
+    /// Since the first event of the capture, by the monotonic clock of the key source,
+    /// so a change of the wall clock does not show.
+    pub time: Duration,
+    /// Wall clock time the event happened at: the one of the first event, advanced by `time`.
+    pub wall: SystemTime,
+    /// Since the previous event.
+    pub delta: Duration,
+}
+
+/// How a [`TraceEvent`] is written.
+#[derive(Serialize, Deserialize)]
+struct JsonTraceEvent {
+    event: String,
+    keys: Vec<String>,
+    time_ms: f64,
+    wall_ms: u64,
+    delta_ms: f64,
+}
+
+impl From<&TraceEvent> for JsonTraceEvent {
+    fn from(event: &TraceEvent) -> Self {
+        let event_name = match event.kind {
+            KeyEventKind::Press => "press",
+            KeyEventKind::Release => "release",
+        };
+
+        Self {
+            event: event_name.into(),
+            keys: event.keys.iter().map(keycode_to_string).collect(),
+            time_ms: event.time.as_secs_f64() * 1000.0,
+            wall_ms: event
+                .wall
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            delta_ms: event.delta.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+impl TryFrom<JsonTraceEvent> for TraceEvent {
+    type Error = String;
+
+    fn try_from(json: JsonTraceEvent) -> std::result::Result<Self, Self::Error> {
+        let kind = match json.event.as_str() {
+            "press" => KeyEventKind::Press,
+            "release" => KeyEventKind::Release,
+            event => return Err(format!("{:?} is not a trace event", event)),
+        };
+        let milliseconds = |ms: f64| {
+            if ms.is_finite() && ms >= 0.0 {
+                Ok(Duration::from_secs_f64(ms / 1000.0))
+            } else {
+                Err(format!("{} is not a time in milliseconds", ms))
+            }
+        };
+
+        Ok(Self {
+            kind,
+            keys: json
+                .keys
+                .iter()
+                .map(|key| parse_keycode_from_string(key))
+                .collect::<std::result::Result<Vec<Keycode>, String>>()?,
+            time: milliseconds(json.time_ms)?,
+            wall: UNIX_EPOCH + Duration::from_millis(json.wall_ms),
+            delta: milliseconds(json.delta_ms)?,
+        })
+    }
+}
+
+impl TraceEvent {
+    /// Parse one line of a JSON Lines trace.
+    pub fn from_json_line(line: &str) -> std::result::Result<Self, String> {
+        let json: JsonTraceEvent = serde_json::from_str(line).map_err(|error| error.to_string())?;
+        Self::try_from(json)
+    }
+}
+
+/// Append `trace_step` to the trace file at `path`.
+pub fn upend_trace(trace_step: TraceStep, path: &Path, trace_plain_style: bool) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|error| Error::io(path, error))?;
+
+    let text = match trace_step {
+        TraceStep::First(keycode) => input_to_string(&keycode),
+        TraceStep::Regular(keycode, duration) => {
+            if trace_plain_style {
+                input_to_string(&keycode)
+            } else {
+                format!("{} after {:?}", input_to_string(&keycode), duration).replace('"', "")
+            }
+        }
+        TraceStep::Empty => return Ok(()),
+    };
+
+    if trace_plain_style {
+        write!(file, "{} ", text)
+    } else {
+        writeln!(file, "{}", text)
+    }
+    .map_err(|error| Error::io(path, error))
+}
+
+/// Append `event` as a line of JSON to the trace file at `path`.
+pub fn append_event(event: &TraceEvent, path: &Path) -> Result<()> {
+    let line = serde_json::to_string(&JsonTraceEvent::from(event))
+        .expect("trace event is always serializable");
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|error| Error::io(path, error))?;
+
+    writeln!(file, "{}", line).map_err(|error| Error::io(path, error))
+}
+
+/// Read every step of a text trace file, or of a plain one when `trace_plain_style`.
+///
+/// A line without a duration starts another capture appended to the same file.
+/// Plain traces keep no times, so every word but the first comes after zero.
+pub fn read_trace(path: &Path, trace_plain_style: bool) -> Result<Vec<TraceStep>> {
+    let contents = fs::read_to_string(path).map_err(|error| Error::io(path, error))?;
+    let line_error = |index: usize, message: String| {
+        Error::parse(path, format!("line {}: {}", index + 1, message))
+    };
+
+    let mut steps = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if trace_plain_style {
+            for word in line.split_whitespace() {
+                let input = parse_input(word).map_err(|error| line_error(index, error))?;
+                steps.push(if steps.is_empty() {
+                    TraceStep::First(input)
+                } else {
+                    TraceStep::Regular(input, Duration::ZERO)
+                });
+            }
+        } else if !line.trim().is_empty() {
+            steps.push(parse_trace_line(line).map_err(|error| line_error(index, error))?);
+        }
+    }
+
+    Ok(steps)
+}
+
+/// Read every event of a JSON Lines trace file.
+pub fn read_events(path: &Path) -> Result<Vec<TraceEvent>> {
+    let contents = fs::read_to_string(path).map_err(|error| Error::io(path, error))?;
+
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            TraceEvent::from_json_line(line)
+                .map_err(|error| Error::parse(path, format!("line {}: {}", index + 1, error)))
+        })
+        .collect()
+}
+
+/// Parse `<input>` or `<input> after <duration>`, the reverse of [`upend_trace`].
+pub fn parse_trace_line(line: &str) -> std::result::Result<TraceStep, String> {
+    match line.trim().split_once(" after ") {
+        Some((input, after)) => Ok(TraceStep::Regular(
+            parse_input(input)?,
+            parse_duration(after.trim())?,
+        )),
+        None => Ok(TraceStep::First(parse_input(line.trim())?)),
+    }
+}
+
+fn parse_input(s: &str) -> std::result::Result<Vec<Keycode>, String> {
+    match parse_input_from_string(s) {
+        Ok(input) if !input.is_empty() => Ok(input),
+        _ => Err(format!("{:?} is not an input", s)),
+    }
+}
+
+/// Parse a [`Duration`] as its `Debug` impl writes it, e.g. `1.2s`, `350ms`, `5µs`, `10ns`.
+pub fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let error = || format!("{:?} is not a duration", s);
+
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .ok_or_else(error)?;
+    let (number, unit) = s.split_at(split);
+    let unit_nanos: u64 = match unit {
+        "ns" => 1,
+        "µs" | "us" => 1_000,
+        "ms" => 1_000_000,
+        "s" => 1_000_000_000,
+        _ => return Err(error()),
+    };
+
+    // whole digits, so nothing is lost to floating point
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if whole.is_empty() || fraction.contains('.') || (number.contains('.') && fraction.is_empty()) {
+        return Err(error());
+    }
+    let whole: u64 = whole.parse().map_err(|_| error())?;
+    let mut fraction_nanos = 0;
+    let mut scale = unit_nanos;
+    for digit in fraction.chars() {
+        scale /= 10;
+        fraction_nanos += u64::from(digit.to_digit(10).ok_or_else(error)?) * scale;
+    }
+
+    let nanos = whole
+        .checked_mul(unit_nanos)
+        .and_then(|nanos| nanos.checked_add(fraction_nanos))
+        .ok_or_else(error)?;
+
+    Ok(Duration::from_nanos(nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TempFile;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("10ns"), Ok(Duration::from_nanos(10)));
+        assert_eq!(parse_duration("5µs"), Ok(Duration::from_micros(5)));
+        assert_eq!(parse_duration("5us"), Ok(Duration::from_micros(5)));
+        assert_eq!(parse_duration("350ms"), Ok(Duration::from_millis(350)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn parse_duration_fractions() {
+        assert_eq!(parse_duration("1.2s"), Ok(Duration::from_millis(1200)));
+        assert_eq!(
+            parse_duration("12.345ms"),
+            Ok(Duration::from_micros(12_345))
+        );
+        assert_eq!(parse_duration("1.5µs"), Ok(Duration::from_nanos(1500)));
+        assert_eq!(
+            parse_duration("1.000000001s"),
+            Ok(Duration::from_nanos(1_000_000_001))
+        );
+    }
+
+    #[test]
+    fn parse_duration_as_debug_writes_it() {
+        for duration in [
+            Duration::ZERO,
+            Duration::from_nanos(7),
+            Duration::from_nanos(120_450),
+            Duration::from_micros(83_001),
+            Duration::from_millis(61_250),
+            Duration::new(3, 1),
+        ] {
+            assert_eq!(parse_duration(&format!("{:?}", duration)), Ok(duration));
+        }
+    }
+
+    #[test]
+    fn parse_duration_bad_input() {
+        for s in [
+            "", "ms", "1.", ".5ms", "1..2s", "1.2.3s", "5m", "5", "-5ms", "5 ms",
+        ] {
+            assert!(parse_duration(s).is_err(), "{:?} parsed", s);
+        }
+    }
+
+    #[test]
+    fn read_trace_line_numbers() {
+        let path = TempFile::new("bad-trace.txt");
+        fs::write(&path, "A\nB after 10ms\n\nC after 5 parsecs\n").unwrap();
+
+        let error = read_trace(&path, false).unwrap_err();
+
+        match error {
+            Error::Parse { message, .. } => assert!(message.starts_with("line 4:"), "{}", message),
+            error => panic!("{:?}", error),
+        }
+    }
+
+    #[test]
+    fn read_trace_steps() {
+        let path = TempFile::new("trace.txt");
+        fs::write(&path, "LShift\nA+LShift after 10ms\nB after 1.5s\n").unwrap();
+
+        let steps = read_trace(&path, false).unwrap();
+
+        assert_eq!(
+            steps,
+            [
+                TraceStep::First(vec![Keycode::LShift]),
+                TraceStep::Regular(vec![Keycode::A, Keycode::LShift], Duration::from_millis(10)),
+                TraceStep::Regular(vec![Keycode::B], Duration::from_millis(1500)),
+            ]
+        );
+    }
+}